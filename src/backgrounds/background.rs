@@ -0,0 +1,27 @@
+use crate::linalg::Vec3;
+use crate::random::sampler::Sampler;
+
+/// A direction picked on a background, with its density per unit solid angle.
+pub struct BackgroundSample {
+    pub direction: Vec3,
+    pub pdf: f32,
+}
+
+/// Light from infinitely far away, seen by every ray that misses the scene.
+pub trait Background: Send + Sync {
+    /// Radiance arriving from the unit vector `direction`.
+    fn radiance(&self, direction: &Vec3) -> Vec3;
+
+    /// Whether the background is bright and uneven enough to be sampled as a light.
+    fn is_light(&self) -> bool {
+        false
+    }
+    /// Picks a direction to sample the background as a light.
+    fn sample(&self, _sampler: &mut dyn Sampler) -> Option<BackgroundSample> {
+        None
+    }
+    /// The density `sample` gives to the unit vector `direction`.
+    fn pdf(&self, _direction: &Vec3) -> f32 {
+        0.0
+    }
+}
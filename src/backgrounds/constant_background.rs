@@ -0,0 +1,18 @@
+use super::background::Background;
+use crate::linalg::Vec3;
+
+pub struct ConstantBackground {
+    color: Vec3,
+}
+
+impl ConstantBackground {
+    pub fn new(color: Vec3) -> Self {
+        Self { color: color }
+    }
+}
+
+impl Background for ConstantBackground {
+    fn radiance(&self, _direction: &Vec3) -> Vec3 {
+        self.color
+    }
+}
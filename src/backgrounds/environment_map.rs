@@ -0,0 +1,168 @@
+use super::background::{Background, BackgroundSample};
+use crate::linalg::Vec3;
+use crate::random::sampler::Sampler;
+
+use std::f32::consts::PI;
+
+/// An equirectangular (latitude-longitude) image around the scene, +y up.
+/// Directions are sampled in proportion to the brightness of the pixels.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    intensity: f32,
+    cos_rotation: f32,
+    sin_rotation: f32,
+    /// Cumulative distribution over rows, `height + 1` entries from 0 to 1.
+    marginal: Vec<f32>,
+    /// Cumulative distribution over each row, `width + 1` entries per row.
+    conditional: Vec<f32>,
+    /// Sampling weight of every pixel over the mean weight, the density in (u, v).
+    density: Vec<f32>,
+}
+
+fn luminance(c: &Vec3) -> f32 {
+    0.2126 * c.0 + 0.7152 * c.1 + 0.0722 * c.2
+}
+
+/// Turns `weights` into a cumulative distribution, uniform if they are all zero.
+fn cumulative(weights: &[f32]) -> Vec<f32> {
+    let mut cdf = Vec::with_capacity(weights.len() + 1);
+    let mut sum = 0.0;
+    cdf.push(0.0);
+    for w in weights {
+        sum += w;
+        cdf.push(sum);
+    }
+    for (i, x) in cdf.iter_mut().enumerate() {
+        *x = if sum > 0.0 {
+            *x / sum
+        } else {
+            i as f32 / weights.len() as f32
+        };
+    }
+    cdf
+}
+
+/// Picks a cell of a cumulative distribution with `r` in `[0, 1)`, returning
+/// the cell and where `r` falls inside it.
+fn sample_cdf(cdf: &[f32], r: f32) -> (usize, f32) {
+    let n = cdf.len() - 1;
+    let i = (cdf.partition_point(|&x| x <= r) - 1).min(n - 1);
+    let width = cdf[i + 1] - cdf[i];
+    let offset = if width > 0.0 {
+        (r - cdf[i]) / width
+    } else {
+        0.5
+    };
+    (i, offset.clamp(0.0, 1.0))
+}
+
+impl EnvironmentMap {
+    /// `data` holds linear RGB row by row from the top, the top row looking
+    /// straight up. `rotation` turns the map about +y, in degrees.
+    pub fn new(data: &[f32], width: usize, height: usize, intensity: f32, rotation: f32) -> Self {
+        let pixels: Vec<Vec3> = data
+            .chunks(3)
+            .take(width * height)
+            .map(|c| Vec3::new(c[0], c[1], c[2]))
+            .collect();
+
+        // Rows near the poles cover less of the sphere.
+        let weights: Vec<f32> = (0..height)
+            .flat_map(|j| {
+                let sin_theta = (PI * (j as f32 + 0.5) / height as f32).sin();
+                let pixels = &pixels;
+                (0..width).map(move |i| luminance(&pixels[j * width + i]).max(0.0) * sin_theta)
+            })
+            .collect();
+        let mean = weights.iter().sum::<f32>() / weights.len() as f32;
+        let row_sums: Vec<f32> = weights.chunks(width).map(|row| row.iter().sum()).collect();
+        let marginal = cumulative(&row_sums);
+        let conditional = weights.chunks(width).flat_map(cumulative).collect();
+        let density = weights
+            .iter()
+            .map(|w| if mean > 0.0 { w / mean } else { 1.0 })
+            .collect();
+
+        let radians = rotation.to_radians();
+        Self {
+            width: width,
+            height: height,
+            pixels: pixels,
+            intensity: intensity,
+            cos_rotation: radians.cos(),
+            sin_rotation: radians.sin(),
+            marginal: marginal,
+            conditional: conditional,
+            density: density,
+        }
+    }
+
+    /// Map coordinates in `[0, 1)` of a world direction.
+    fn direction_to_uv(&self, direction: &Vec3) -> (f32, f32) {
+        let x = self.cos_rotation * direction.0 - self.sin_rotation * direction.2;
+        let z = self.sin_rotation * direction.0 + self.cos_rotation * direction.2;
+        let phi = z.atan2(x);
+        let theta = direction.1.clamp(-1.0, 1.0).acos();
+        ((phi + PI) / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = 2.0 * PI * u - PI;
+        let theta = PI * v;
+        let x = theta.sin() * phi.cos();
+        let z = theta.sin() * phi.sin();
+        Vec3::new(
+            self.cos_rotation * x + self.sin_rotation * z,
+            theta.cos(),
+            -self.sin_rotation * x + self.cos_rotation * z,
+        )
+    }
+
+    fn pixel_index(&self, u: f32, v: f32) -> usize {
+        let i = ((u * self.width as f32) as usize).min(self.width - 1);
+        let j = ((v * self.height as f32) as usize).min(self.height - 1);
+        j * self.width + i
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        self.intensity * self.pixels[self.pixel_index(u, v)]
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<BackgroundSample> {
+        let (s, t) = sampler.get_2d();
+        let (j, dv) = sample_cdf(&self.marginal, t);
+        let row = &self.conditional[j * (self.width + 1)..(j + 1) * (self.width + 1)];
+        let (i, du) = sample_cdf(row, s);
+        let u = (i as f32 + du) / self.width as f32;
+        let v = (j as f32 + dv) / self.height as f32;
+        let direction = self.uv_to_direction(u, v);
+        let pdf = self.pdf(&direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BackgroundSample {
+            direction: direction,
+            pdf: pdf,
+        })
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // The map covers 2 pi by pi radians and a patch of it a solid angle
+        // shrinking with sin(theta).
+        self.density[self.pixel_index(u, v)] / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,30 @@
+use super::background::Background;
+use crate::linalg::Vec3;
+
+/// Blends from `bottom` straight down to `top` straight up, the sky of the first book.
+pub struct GradientBackground {
+    bottom: Vec3,
+    top: Vec3,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Vec3, top: Vec3) -> Self {
+        Self {
+            bottom: bottom,
+            top: top,
+        }
+    }
+}
+
+impl Default for GradientBackground {
+    fn default() -> Self {
+        Self::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for GradientBackground {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let t = 0.5 * (direction.1 + 1.0);
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
@@ -0,0 +1,11 @@
+pub use self::background::{Background, BackgroundSample};
+pub use self::constant_background::ConstantBackground;
+pub use self::environment_map::EnvironmentMap;
+pub use self::gradient_background::GradientBackground;
+pub use self::sky_background::SkyBackground;
+
+pub mod background;
+mod constant_background;
+mod environment_map;
+mod gradient_background;
+mod sky_background;
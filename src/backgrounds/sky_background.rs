@@ -0,0 +1,193 @@
+use super::background::{Background, BackgroundSample};
+use crate::linalg::{Vec3, ONB};
+use crate::random::sampler::Sampler;
+use crate::random::utils::{cone_pdf, random_in_cone, random_unit_vector};
+
+/// Brings the luminance of the sky model, in kcd/m², to the scale of scene lights.
+const SKY_SCALE: f32 = 0.05;
+
+/// Chance of sampling the sun rather than the whole sphere when it is up.
+const SUN_PROBABILITY: f32 = 0.5;
+
+/// Coefficients of the Perez sky luminance distribution.
+#[derive(Clone, Copy)]
+struct Perez([f32; 5]);
+
+impl Perez {
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The Preetham daylight model for a given sun position and haziness, with a
+/// sun disc on top. Directions below the horizon see the horizon dimmed by `ground`.
+pub struct SkyBackground {
+    sun_direction: Vec3,
+    zenith: [f32; 3],
+    perez: [Perez; 3],
+    /// The Perez function at the zenith, which the zenith values are relative to.
+    perez_zenith: [f32; 3],
+    intensity: f32,
+    ground: Vec3,
+    cos_sun_radius: f32,
+    sun_radiance: Vec3,
+}
+
+impl SkyBackground {
+    /// `sun_size` is the angular diameter of the sun in degrees and
+    /// `sun_intensity` the irradiance it gives a surface facing it.
+    pub fn new(
+        sun_direction: Vec3,
+        turbidity: f32,
+        intensity: f32,
+        ground: Vec3,
+        sun_size: f32,
+        sun_color: Vec3,
+        sun_intensity: f32,
+    ) -> Self {
+        let sun_direction = Vec3::unit(&sun_direction);
+        let t = turbidity;
+        let theta_s = sun_direction
+            .1
+            .clamp(-1.0, 1.0)
+            .acos()
+            .min(std::f32::consts::FRAC_PI_2);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let theta = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+        let chromaticity = |m: [[f32; 4]; 3]| -> f32 {
+            let row = |r: [f32; 4]| -> f32 { r.iter().zip(theta.iter()).map(|(a, b)| a * b).sum() };
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez = [
+            Perez([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            Perez([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            Perez([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+        let perez_zenith = [
+            perez[0].eval(1.0, theta_s),
+            perez[1].eval(1.0, theta_s),
+            perez[2].eval(1.0, theta_s),
+        ];
+
+        let sun_radius = (0.5 * sun_size).to_radians();
+        let cos_sun_radius = sun_radius.cos();
+        let sun_solid_angle = 1.0 / cone_pdf(cos_sun_radius);
+        Self {
+            sun_direction: sun_direction,
+            zenith: [zenith_luminance.max(0.0), zenith_x, zenith_y],
+            perez: perez,
+            perez_zenith: perez_zenith,
+            intensity: intensity,
+            ground: ground,
+            cos_sun_radius: cos_sun_radius,
+            sun_radiance: (sun_intensity / sun_solid_angle) * sun_color,
+        }
+    }
+
+    fn sun_is_up(&self) -> bool {
+        self.sun_direction.1 > 0.0
+    }
+
+    fn sky(&self, direction: &Vec3) -> Vec3 {
+        // Keep away from the horizon, where the model blows up.
+        let cos_theta = direction.1.max(0.01);
+        let cos_gamma = Vec3::dot(direction, &self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let value =
+            |i: usize| self.zenith[i] * self.perez[i].eval(cos_theta, gamma) / self.perez_zenith[i];
+        let luminance = value(0) * SKY_SCALE * self.intensity;
+        let x = value(1);
+        let y = value(2);
+        if luminance <= 0.0 || y <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+
+        // xyY to XYZ to linear sRGB.
+        let cx = x / y * luminance;
+        let cz = (1.0 - x - y) / y * luminance;
+        Vec3::new(
+            (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+            (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+            (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+        )
+    }
+}
+
+impl Background for SkyBackground {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        if direction.1 < 0.0 {
+            let horizon = Vec3::new(direction.0, 0.0, direction.2);
+            if horizon.squared_length() == 0.0 {
+                return self.ground * self.sky(&Vec3::new(1.0, 0.0, 0.0));
+            }
+            return self.ground * self.sky(&Vec3::unit(&horizon));
+        }
+        let mut radiance = self.sky(direction);
+        if self.sun_is_up() && Vec3::dot(direction, &self.sun_direction) >= self.cos_sun_radius {
+            radiance += self.sun_radiance;
+        }
+        radiance
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<BackgroundSample> {
+        let direction = if self.sun_is_up() && sampler.get_1d() < SUN_PROBABILITY {
+            ONB::from_w(&self.sun_direction).local(&random_in_cone(sampler, self.cos_sun_radius))
+        } else {
+            random_unit_vector(sampler)
+        };
+        Some(BackgroundSample {
+            pdf: self.pdf(&direction),
+            direction: direction,
+        })
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        let uniform = 0.25 * std::f32::consts::FRAC_1_PI;
+        if !self.sun_is_up() {
+            return uniform;
+        }
+        let mut pdf = (1.0 - SUN_PROBABILITY) * uniform;
+        if Vec3::dot(direction, &self.sun_direction) >= self.cos_sun_radius {
+            pdf += SUN_PROBABILITY * cone_pdf(self.cos_sun_radius);
+        }
+        pdf
+    }
+}
@@ -1,6 +1,10 @@
 use crate::linalg::{Ray, Vec3};
-use rand::Rng;
+use crate::random::sampler::Sampler;
 
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+/// A thin-lens camera with a shutter interval for motion blur.
+#[derive(Clone)]
 pub struct Camera {
     origin: Vec3,
     lower_left_corner: Vec3,
@@ -11,20 +15,25 @@ pub struct Camera {
     #[allow(dead_code)]
     w: Vec3,
     lens_radius: f32,
+    pub aspect: f32,
     t_open: f32,
     t_close: f32,
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut p: Vec3;
-    let mut rng = rand::thread_rng();
-    loop {
-        p = 2.0 * Vec3(rng.gen(), rng.gen(), 0.0) - Vec3(0.0, 0.0, 0.0);
-        if Vec3::dot(&p, &p) < 1.0 {
-            break;
-        }
+/// A point spread uniformly over the unit disk, by Shirley and Chiu's concentric
+/// mapping of the square.
+pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
+    let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
     }
-    p
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+    Vec3(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 impl Camera {
@@ -59,15 +68,24 @@ impl Camera {
             v: v,
             w: w,
             lens_radius: aperture / 2.0,
+            aspect: aspect,
             t_open: t_open,
             t_close: t_close,
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    /// Widens or narrows the frame horizontally, keeping the vertical field of view.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        let center = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+        self.horizontal = (aspect / self.aspect) * self.horizontal;
+        self.lower_left_corner = center - 0.5 * self.horizontal - 0.5 * self.vertical;
+        self.aspect = aspect;
+    }
+
+    pub fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disk(sampler);
         let offset = self.u * rd.0 + self.v * rd.1;
-        let time = self.t_open + rand::random::<f32>() * (self.t_close - self.t_open);
+        let time = self.t_open + sampler.get_1d() * (self.t_close - self.t_open);
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
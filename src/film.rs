@@ -0,0 +1,512 @@
+//! Reconstructing the pixels of an image from the samples around them.
+//!
+//! Every sample is splatted into all the pixels its filter reaches, weighted by the
+//! filter at the distance between the sample and the pixel's centre, and every pixel
+//! ends up as the weighted mean of what it got. Pixel `(x, y)` covers `[x, x + 1)`
+//! and `[y, y + 1)`, with `y` going up from the bottom row.
+
+use crate::linalg::Vec3;
+use crate::render::{AdaptiveOptions, Framebuffer};
+
+use std::f32::consts::PI;
+use std::fs;
+
+/// The first bytes of a checkpoint file.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCHECK1";
+
+/// The shape of a reconstruction filter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    /// Every sample within the radius counts the same. With a radius of half a
+    /// pixel, pixels are the plain mean of their own samples.
+    Box,
+    /// Falls off linearly to zero at the radius.
+    Tent,
+    /// A Gaussian with a standard deviation of a third of the radius, shifted down to
+    /// reach zero at the radius.
+    Gaussian,
+    /// Mitchell and Netravali's cubic with B = C = 1/3, sharper than the Gaussian at
+    /// the cost of slight ringing.
+    Mitchell,
+    /// The four-term Blackman-Harris window.
+    BlackmanHarris,
+}
+
+impl FilterKind {
+    /// Looks a filter up by the name used in scene files and on the command line.
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" | "triangle" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "blackman_harris" | "blackman-harris" => Some(FilterKind::BlackmanHarris),
+            _ => None,
+        }
+    }
+
+    /// The radius the filter has unless one is given.
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::BlackmanHarris => 2.0,
+        }
+    }
+}
+
+/// A separable reconstruction filter reaching `radius` pixels on each axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind: kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// The weight of a sample `(dx, dy)` away from a pixel's centre.
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius;
+        // Half open, so that a sample on the edge between two pixels only falls in
+        // one of them.
+        if x < -r || x >= r {
+            return 0.0;
+        }
+        let x = x.abs();
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x / r;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+            FilterKind::BlackmanHarris => {
+                let t = 2.0 * PI * (x / (2.0 * r) + 0.5);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+
+    /// How many pixels beyond its own a sample may reach on each side.
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).ceil().max(0.0) as usize
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+/// A rectangle of pixels, `x0..x1` by `y0..y1`.
+#[derive(Debug, Copy, Clone)]
+struct Bounds {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl Bounds {
+    fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    /// Index of pixel `(x, y)` in a buffer of the rectangle, top row first.
+    fn index(&self, x: usize, y: usize) -> usize {
+        (self.y1 - 1 - y) * self.width() + (x - self.x0)
+    }
+
+    fn area(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+/// What the samples of one tile add to the film: their splats over the tile and the
+/// margin its filter reaches, and the statistics of the tile's own pixels.
+pub struct FilmTile {
+    filter: Filter,
+    /// The pixels the tile takes samples for.
+    own: Bounds,
+    /// The pixels those samples reach, clipped to the film.
+    reach: Bounds,
+    /// Filter-weighted radiance, three values per pixel of `reach`.
+    sums: Vec<f32>,
+    weights: Vec<f32>,
+    /// Luminance and squared luminance sums and sample counts of the pixels of `own`.
+    luminance: Vec<f32>,
+    squares: Vec<f32>,
+    counts: Vec<u32>,
+}
+
+impl FilmTile {
+    /// Adds a sample taken at `offset` inside pixel `(x, y)`, both coordinates of the
+    /// offset in `[0, 1)`.
+    pub fn add_sample(&mut self, x: usize, y: usize, offset: (f32, f32), radiance: Vec3) {
+        let own = self.own.index(x, y);
+        let l = radiance.luminance();
+        self.luminance[own] += l;
+        self.squares[own] += l * l;
+        self.counts[own] += 1;
+
+        // Distances are taken from whole pixels apart, which `x as f32 + offset` could
+        // round into the next pixel.
+        let margin = self.filter.margin();
+        let distance = |p: usize, q: usize, offset: f32| (p as f32 - q as f32) + offset - 0.5;
+        let ys = y.saturating_sub(margin).max(self.reach.y0)..(y + margin + 1).min(self.reach.y1);
+        for py in ys {
+            let wy = self.filter.evaluate_1d(distance(y, py, offset.1));
+            if wy == 0.0 {
+                continue;
+            }
+            let xs =
+                x.saturating_sub(margin).max(self.reach.x0)..(x + margin + 1).min(self.reach.x1);
+            for px in xs {
+                let w = wy * self.filter.evaluate_1d(distance(x, px, offset.0));
+                if w == 0.0 {
+                    continue;
+                }
+                let i = self.reach.index(px, py);
+                self.sums[i * 3] += w * radiance.0;
+                self.sums[i * 3 + 1] += w * radiance.1;
+                self.sums[i * 3 + 2] += w * radiance.2;
+                self.weights[i] += w;
+            }
+        }
+    }
+}
+
+/// The image being rendered, as running sums over every sample taken so far.
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    sums: Vec<f32>,
+    weights: Vec<f32>,
+    /// Per-pixel statistics of the samples taken for each pixel, unfiltered, for
+    /// adaptive sampling.
+    luminance: Vec<f32>,
+    squares: Vec<f32>,
+    counts: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            width: width,
+            height: height,
+            filter: filter,
+            sums: vec![0.0; width * height * 3],
+            weights: vec![0.0; width * height],
+            luminance: vec![0.0; width * height],
+            squares: vec![0.0; width * height],
+            counts: vec![0; width * height],
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        Bounds {
+            x0: 0,
+            y0: 0,
+            x1: self.width,
+            y1: self.height,
+        }
+    }
+
+    /// An empty tile for the pixels `start_x..start_x + width` by
+    /// `start_y..start_y + height`.
+    pub fn tile(&self, start_x: usize, start_y: usize, width: usize, height: usize) -> FilmTile {
+        let own = Bounds {
+            x0: start_x,
+            y0: start_y,
+            x1: start_x + width,
+            y1: start_y + height,
+        };
+        let margin = self.filter.margin();
+        let reach = Bounds {
+            x0: own.x0.saturating_sub(margin),
+            y0: own.y0.saturating_sub(margin),
+            x1: (own.x1 + margin).min(self.width),
+            y1: (own.y1 + margin).min(self.height),
+        };
+        FilmTile {
+            filter: self.filter,
+            own: own,
+            reach: reach,
+            sums: vec![0.0; reach.area() * 3],
+            weights: vec![0.0; reach.area()],
+            luminance: vec![0.0; own.area()],
+            squares: vec![0.0; own.area()],
+            counts: vec![0; own.area()],
+        }
+    }
+
+    /// Adds what a tile took. Tiles overlap where their filters reach, so adding them
+    /// in the same order every time keeps the sums, and the image, the same.
+    pub fn add_tile(&mut self, tile: &FilmTile) {
+        let film = self.bounds();
+        for y in tile.reach.y0..tile.reach.y1 {
+            for x in tile.reach.x0..tile.reach.x1 {
+                let (pixel, i) = (film.index(x, y), tile.reach.index(x, y));
+                for c in 0..3 {
+                    self.sums[pixel * 3 + c] += tile.sums[i * 3 + c];
+                }
+                self.weights[pixel] += tile.weights[i];
+            }
+        }
+        for y in tile.own.y0..tile.own.y1 {
+            for x in tile.own.x0..tile.own.x1 {
+                let (pixel, i) = (film.index(x, y), tile.own.index(x, y));
+                self.luminance[pixel] += tile.luminance[i];
+                self.squares[pixel] += tile.squares[i];
+                self.counts[pixel] += tile.counts[i];
+            }
+        }
+    }
+
+    /// Whether the standard error of the pixel's luminance is below the threshold.
+    fn converged(&self, pixel: usize, adaptive: &AdaptiveOptions) -> bool {
+        let n = self.counts[pixel] as usize;
+        if n < adaptive.min_samples.max(2) {
+            return false;
+        }
+        let n = n as f32;
+        let mean = self.luminance[pixel] / n;
+        let variance = ((self.squares[pixel] - n * mean * mean) / (n - 1.0)).max(0.0);
+        // The floor keeps nearly black pixels from taking every sample.
+        (variance / n).sqrt() <= adaptive.threshold * mean.max(0.01)
+    }
+
+    /// The pixels of a tile, in the order the tile is rendered: top row first.
+    fn tile_pixels(
+        &self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let film = self.bounds();
+        (start_y..start_y + height)
+            .rev()
+            .flat_map(move |y| (start_x..start_x + width).map(move |x| film.index(x, y)))
+    }
+
+    /// Which pixels of a tile have had fewer than `target` samples and still need
+    /// them, top row first.
+    pub fn active(
+        &self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        target: u32,
+        adaptive: Option<&AdaptiveOptions>,
+    ) -> Vec<bool> {
+        self.tile_pixels(start_x, start_y, width, height)
+            .map(|pixel| {
+                self.counts[pixel] < target && adaptive.is_none_or(|a| !self.converged(pixel, a))
+            })
+            .collect()
+    }
+
+    /// How many samples every pixel of a tile has had so far, top row first.
+    pub fn counts(&self, start_x: usize, start_y: usize, width: usize, height: usize) -> Vec<u32> {
+        self.tile_pixels(start_x, start_y, width, height)
+            .map(|pixel| self.counts[pixel])
+            .collect()
+    }
+
+    /// The weighted mean of every pixel; pixels no sample reached stay black.
+    pub fn resolve(&self) -> Framebuffer {
+        let mut frame = Framebuffer::new(self.width, self.height);
+        for (pixel, &w) in self.weights.iter().enumerate() {
+            // Negative lobes can leave a sparsely sampled pixel with no weight to
+            // speak of.
+            if w > 1e-6 {
+                for c in 0..3 {
+                    frame.data[pixel * 3 + c] = self.sums[pixel * 3 + c] / w;
+                }
+            }
+        }
+        frame.samples.copy_from_slice(&self.counts);
+        frame
+    }
+
+    /// Saves the sums so far, with the `samples_done` they were at, so that an
+    /// interrupted render can pick up from them. `key` tells the render they belong to
+    /// from any other. The file is written next to `path` first, then moved over it.
+    pub fn write_checkpoint(
+        &self,
+        path: &str,
+        key: u64,
+        samples_done: usize,
+    ) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(40 + self.weights.len() * 28);
+        bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        for v in [
+            key,
+            self.width as u64,
+            self.height as u64,
+            samples_done as u64,
+        ] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        for values in [&self.sums, &self.weights, &self.luminance, &self.squares] {
+            for x in values.iter() {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        for n in &self.counts {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        let temporary = format!("{}.tmp", path);
+        fs::write(&temporary, &bytes)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|why| format!("couldn't write {}: {}", path, why))
+    }
+
+    /// Loads a checkpoint of the render `key` stands for, and the samples per pixel it
+    /// was at, for a `width` by `height` film reconstructed with `filter`.
+    pub fn read_checkpoint(
+        path: &str,
+        key: u64,
+        width: usize,
+        height: usize,
+        filter: Filter,
+    ) -> Result<(Film, usize), String> {
+        let bytes = fs::read(path).map_err(|why| format!("couldn't read {}: {}", path, why))?;
+        let invalid = |why: &str| format!("couldn't resume from {}: {}", path, why);
+        let n = width * height;
+        if bytes.len() != 40 + n * 28 || &bytes[..8] != CHECKPOINT_MAGIC {
+            return Err(invalid("not a checkpoint of this image"));
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        if u64_at(8) != key || u64_at(16) != width as u64 || u64_at(24) != height as u64 {
+            return Err(invalid("the scene or the render settings have changed"));
+        }
+        let samples_done = u64_at(32) as usize;
+        let mut words = bytes[40..].chunks_exact(4).map(|x| x.try_into().unwrap());
+        let mut film = Film::new(width, height, filter);
+        for values in [
+            &mut film.sums,
+            &mut film.weights,
+            &mut film.luminance,
+            &mut film.squares,
+        ] {
+            for x in values.iter_mut() {
+                *x = f32::from_le_bytes(words.next().unwrap());
+            }
+        }
+        for x in film.counts.iter_mut() {
+            *x = u32::from_le_bytes(words.next().unwrap());
+        }
+        Ok((film, samples_done))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: u64 = 0x5eed;
+
+    fn temp_file(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        let file = format!("raytrace-{}-{}", std::process::id(), name);
+        dir.join(file).to_string_lossy().into_owned()
+    }
+
+    /// A 5x4 film with a few samples in two overlapping tiles.
+    fn sampled_film() -> Film {
+        let mut film = Film::new(5, 4, Filter::new(FilterKind::Mitchell));
+        for (x0, width) in [(0, 3), (3, 2)] {
+            let mut tile = film.tile(x0, 0, width, 4);
+            for y in 0..4 {
+                for x in x0..x0 + width {
+                    let radiance = Vec3::new(x as f32, y as f32 * 0.5, 1.0 / (1 + x + y) as f32);
+                    tile.add_sample(x, y, (0.25, 0.75), radiance);
+                    tile.add_sample(x, y, (0.6, 0.1), 2.0 * radiance);
+                }
+            }
+            film.add_tile(&tile);
+        }
+        film
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let film = sampled_film();
+        let path = temp_file("round_trip.ckpt");
+        film.write_checkpoint(&path, KEY, 2).unwrap();
+        let read = Film::read_checkpoint(&path, KEY, 5, 4, film.filter);
+        std::fs::remove_file(&path).unwrap();
+
+        let (read, samples_done) = read.unwrap();
+        assert_eq!(samples_done, 2);
+        assert_eq!(read.sums, film.sums);
+        assert_eq!(read.weights, film.weights);
+        assert_eq!(read.luminance, film.luminance);
+        assert_eq!(read.squares, film.squares);
+        assert_eq!(read.counts, film.counts);
+    }
+
+    #[test]
+    fn checkpoint_of_another_render_is_rejected() {
+        let film = sampled_film();
+        let path = temp_file("other_render.ckpt");
+        film.write_checkpoint(&path, KEY, 2).unwrap();
+        let other_key = Film::read_checkpoint(&path, KEY + 1, 5, 4, film.filter);
+        let other_size = Film::read_checkpoint(&path, KEY, 6, 4, film.filter);
+        let transposed = Film::read_checkpoint(&path, KEY, 4, 5, film.filter);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(other_key.err().unwrap().contains("have changed"));
+        assert!(other_size
+            .err()
+            .unwrap()
+            .contains("not a checkpoint of this image"));
+        assert!(transposed.err().unwrap().contains("have changed"));
+    }
+
+    #[test]
+    fn file_that_is_not_a_checkpoint_is_rejected() {
+        let path = temp_file("not_a_checkpoint.ckpt");
+        std::fs::write(&path, vec![0; 40 + 5 * 4 * 28]).unwrap();
+        let read = Film::read_checkpoint(&path, KEY, 5, 4, Filter::default());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(read
+            .err()
+            .unwrap()
+            .contains("not a checkpoint of this image"));
+    }
+}
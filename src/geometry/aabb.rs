@@ -73,6 +73,23 @@ impl AABB {
         }
         true
     }
+
+    /// Slab test against a ray given by its origin and the reciprocal of its direction.
+    #[inline(always)]
+    pub fn hit_inverse(&self, origin: &Vec3, inv_direction: &Vec3, t_min: f32, t_max: f32) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inv_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inv_direction[axis];
+            t_min = fmax(t_min, fmin(t0, t1));
+            t_max = fmin(t_max, fmax(t0, t1));
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
@@ -1,11 +1,12 @@
 use super::aabb::AABB;
 use super::flip_normals::FlipNormals;
-use super::hittable::{HitRecord, Hittable};
+use super::hittable::{HitRecord, Hittable, LightSample};
 use super::hittable_list::HittableList;
 use super::rect::{XYRect, XZRect, YZRect};
 use crate::materials::Material;
 
 use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
@@ -74,13 +75,65 @@ impl BoxObject {
             faces: faces,
         }
     }
+
+    /// The faces turned towards `origin`, the only ones it can see, at most one
+    /// on each axis. The faces were added as +z, -z, +y, -y, +x and -x.
+    fn faces_towards(&self, origin: &Vec3) -> Vec<&Arc<dyn Hittable>> {
+        self.faces
+            .list
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                let axis = 2 - i / 2;
+                if i % 2 == 0 {
+                    origin[axis] > self.p_max[axis]
+                } else {
+                    origin[axis] < self.p_min[axis]
+                }
+            })
+            .map(|(_, face)| face)
+            .collect()
+    }
 }
 
 impl Hittable for BoxObject {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.faces.hit(r, t_min, t_max)
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        self.faces.hit(r, t_min, t_max, sampler)
     }
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(&self.p_min, &self.p_max))
     }
+
+    fn is_light(&self) -> bool {
+        self.faces.list[0].is_light()
+    }
+
+    // Picks one of the faces turned towards `origin`. Rays from outside enter
+    // the box through exactly one of them, so their densities add up.
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let faces = self.faces_towards(origin);
+        if faces.is_empty() {
+            return None;
+        }
+        let index = ((sampler.get_1d() * faces.len() as f32) as usize).min(faces.len() - 1);
+        faces[index]
+            .sample_towards(origin, sampler)
+            .map(|sample| LightSample {
+                point: sample.point,
+                pdf: sample.pdf / faces.len() as f32,
+            })
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        let faces = self.faces_towards(origin);
+        let sum: f32 = faces
+            .iter()
+            .map(|face| face.pdf_towards(origin, direction))
+            .sum();
+        if faces.is_empty() {
+            0.0
+        } else {
+            sum / faces.len() as f32
+        }
+    }
 }
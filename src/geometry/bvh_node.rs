@@ -1,97 +1,396 @@
 use super::aabb::{surrounding_box, AABB};
 use super::hittable::{HitRecord, Hittable};
-use crate::linalg::Ray;
+use crate::linalg::{Ray, Vec3};
+use crate::random::pcg::Pcg32;
+use crate::random::sampler::Sampler;
+use crate::stats;
 
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+/// How a BVH decides where to split a set of primitives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BVHSplit {
+    /// Sort along a random axis and split the list in half.
+    Midpoint,
+    /// Binned surface area heuristic.
+    SAH,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BVHOptions {
+    pub split: BVHSplit,
+    /// Most primitives the SAH builder puts in one leaf. The midpoint builder ignores it.
+    pub max_leaf_size: usize,
+}
+
+impl Default for BVHOptions {
+    fn default() -> Self {
+        Self {
+            split: BVHSplit::SAH,
+            max_leaf_size: 4,
+        }
+    }
+}
+
+/// Shape of a built tree, to compare builders.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BVHStats {
+    pub build_time: Duration,
+    pub n_primitives: usize,
+    /// Every node of the tree, leaves included.
+    pub n_nodes: usize,
+    pub n_leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+}
+
+impl BVHStats {
+    pub fn mean_leaf_size(&self) -> f32 {
+        if self.n_leaves == 0 {
+            0.0
+        } else {
+            self.n_primitives as f32 / self.n_leaves as f32
+        }
+    }
+
+    fn add_leaf(&mut self, size: usize, depth: usize) {
+        self.n_nodes += 1;
+        self.n_leaves += 1;
+        self.n_primitives += size;
+        self.max_leaf_size = self.max_leaf_size.max(size);
+        self.max_depth = self.max_depth.max(depth);
+    }
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} nodes, {} leaves ({:.2} primitives on average, {} at most), depth {}, built in {:?}",
+            self.n_primitives,
+            self.n_nodes,
+            self.n_leaves,
+            self.mean_leaf_size(),
+            self.max_leaf_size,
+            self.max_depth,
+            self.build_time
+        )
+    }
+}
+
+/// One node of a [`BVH`].
+#[derive(Debug, Copy, Clone)]
 pub struct BVHNode {
     pub bbox: AABB,
-    left: Arc<dyn Hittable>,
-    right: Arc<dyn Hittable>,
+    /// First primitive of a leaf, or the index of the second child of an
+    /// interior node. The first child always follows its parent.
+    offset: u32,
+    /// Number of primitives in a leaf, zero for interior nodes.
+    count: u32,
+    /// Axis an interior node was split along, to visit the nearer child first.
+    axis: u8,
+}
+
+/// A bounding volume hierarchy stored depth-first in one array.
+pub struct BVH {
+    nodes: Vec<BVHNode>,
+    primitives: Vec<Arc<dyn Hittable>>,
+}
+
+/// Deeper trees are cut into leaves so traversal can use a fixed-size stack.
+const MAX_DEPTH: usize = 64;
+
+const SAH_BINS: usize = 16;
+
+struct Primitive {
+    shape: Arc<dyn Hittable>,
+    bbox: AABB,
+    centroid: Vec3,
+}
+
+fn surface_area(bbox: &AABB) -> f32 {
+    let d = bbox.max - bbox.min;
+    2.0 * (d.0 * d.1 + d.1 * d.2 + d.2 * d.0)
 }
 
-fn box_x_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> std::cmp::Ordering {
-    let left_box = a.bounding_box(0.0, 0.0).unwrap();
-    let right_box = b.bounding_box(0.0, 0.0).unwrap();
-    if left_box.min.0 - right_box.min.0 < 0.0 {
-        std::cmp::Ordering::Less
-    } else {
-        std::cmp::Ordering::Greater
+fn bounds(prims: &[Primitive]) -> AABB {
+    let mut bbox = prims[0].bbox;
+    for p in prims.iter().skip(1) {
+        bbox = surrounding_box(&bbox, &p.bbox);
     }
+    bbox
 }
 
-fn box_y_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> std::cmp::Ordering {
-    let left_box = a.bounding_box(0.0, 0.0).unwrap();
-    let right_box = b.bounding_box(0.0, 0.0).unwrap();
-    if left_box.min.1 - right_box.min.1 < 0.0 {
-        std::cmp::Ordering::Less
-    } else {
-        std::cmp::Ordering::Greater
+fn bin_index(x: f32, min: f32, extent: f32) -> usize {
+    let b = ((x - min) / extent * SAH_BINS as f32) as usize;
+    b.min(SAH_BINS - 1)
+}
+
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b,
     }
 }
 
-fn box_z_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> std::cmp::Ordering {
-    let left_box = a.bounding_box(0.0, 0.0).unwrap();
-    let right_box = b.bounding_box(0.0, 0.0).unwrap();
-    if left_box.min.2 - right_box.min.2 < 0.0 {
-        std::cmp::Ordering::Less
-    } else {
-        std::cmp::Ordering::Greater
+/// Moves the primitives matching `left` to the front, returning how many there are.
+fn partition<F: Fn(&Primitive) -> bool>(prims: &mut [Primitive], left: F) -> usize {
+    let mut mid = 0;
+    for i in 0..prims.len() {
+        if left(&prims[i]) {
+            prims.swap(i, mid);
+            mid += 1;
+        }
     }
+    mid
 }
 
-impl BVHNode {
-    pub fn new(list: &mut [Arc<dyn Hittable>], time0: f32, time1: f32) -> Self {
-        let axis = (3.0 * rand::random::<f32>()) as usize;
-        if axis == 0 {
-            list.sort_by(box_x_compare);
-        } else if axis == 1 {
-            list.sort_by(box_y_compare);
-        } else {
-            list.sort_by(box_z_compare);
+/// Finds the cheapest split between bins on any axis, counting one unit for
+/// every primitive test and one for the traversal step. Returns the axis, the
+/// last bin on the left and the cost.
+fn find_sah_split(
+    prims: &[Primitive],
+    bbox: &AABB,
+    centroid_box: &AABB,
+) -> Option<(usize, usize, f32)> {
+    let parent_area = surface_area(bbox);
+    let mut best: Option<(usize, usize, f32)> = None;
+    for axis in 0..3 {
+        let extent = centroid_box.max[axis] - centroid_box.min[axis];
+        if extent <= 0.0 {
+            continue;
+        }
+        let mut counts = [0_usize; SAH_BINS];
+        let mut boxes: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+        for p in prims.iter() {
+            let b = bin_index(p.centroid[axis], centroid_box.min[axis], extent);
+            counts[b] += 1;
+            boxes[b] = merge(boxes[b], Some(p.bbox));
+        }
+
+        let mut right_area = [0.0_f32; SAH_BINS];
+        let mut right_count = [0_usize; SAH_BINS];
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc = merge(acc, boxes[b]);
+            count += counts[b];
+            right_area[b] = acc.map_or(0.0, |x| surface_area(&x));
+            right_count[b] = count;
         }
-        let left: Arc<dyn Hittable>;
-        let right: Arc<dyn Hittable>;
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+        for b in 0..(SAH_BINS - 1) {
+            acc = merge(acc, boxes[b]);
+            count += counts[b];
+            if count == 0 || right_count[b + 1] == 0 {
+                continue;
+            }
+            let left_area = acc.map_or(0.0, |x| surface_area(&x));
+            let cost = 1.0
+                + (left_area * count as f32 + right_area[b + 1] * right_count[b + 1] as f32)
+                    / parent_area;
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+    best
+}
 
-        let size = list.len();
+/// Lays the tree out depth-first while it is being built.
+struct Builder<'a> {
+    options: &'a BVHOptions,
+    /// Picks the axes of the midpoint split, seeded so that every build is the same.
+    rng: Pcg32,
+    nodes: Vec<BVHNode>,
+    primitives: Vec<Arc<dyn Hittable>>,
+    stats: BVHStats,
+}
 
-        if size == 1 {
-            left = list[0].clone();
-            right = list[0].clone();
-        } else if size == 2 {
-            left = list[0].clone();
-            right = list[1].clone();
-        } else {
-            let half_size = size / 2;
-            left = Arc::new(BVHNode::new(&mut list[..half_size], time0, time1));
-            right = Arc::new(BVHNode::new(&mut list[half_size..size], time0, time1));
+impl<'a> Builder<'a> {
+    fn push_leaf(&mut self, prims: &[Primitive], bbox: AABB, depth: usize) {
+        self.stats.add_leaf(prims.len(), depth);
+        self.nodes.push(BVHNode {
+            bbox: bbox,
+            offset: self.primitives.len() as u32,
+            count: prims.len() as u32,
+            axis: 0,
+        });
+        for p in prims {
+            self.primitives.push(p.shape.clone());
         }
-        let left_box = left.bounding_box(time0, time1).unwrap();
-        let right_box = right.bounding_box(time0, time1).unwrap();
-        Self {
-            bbox: surrounding_box(&left_box, &right_box),
-            left: left,
-            right: right,
+    }
+
+    fn push_interior(
+        &mut self,
+        prims: &mut [Primitive],
+        bbox: AABB,
+        axis: usize,
+        mid: usize,
+        depth: usize,
+    ) {
+        self.stats.n_nodes += 1;
+        let index = self.nodes.len();
+        self.nodes.push(BVHNode {
+            bbox: bbox,
+            offset: 0,
+            count: 0,
+            axis: axis as u8,
+        });
+        let (left, right) = prims.split_at_mut(mid);
+        self.build(left, depth + 1);
+        self.nodes[index].offset = self.nodes.len() as u32;
+        self.build(right, depth + 1);
+    }
+
+    fn build(&mut self, prims: &mut [Primitive], depth: usize) {
+        let bbox = bounds(prims);
+        let n = prims.len();
+        if n == 1 || depth + 1 >= MAX_DEPTH {
+            return self.push_leaf(prims, bbox, depth);
+        }
+        match self.options.split {
+            BVHSplit::Midpoint => {
+                let axis = self.rng.below(3);
+                prims.sort_by(|a, b| a.bbox.min[axis].total_cmp(&b.bbox.min[axis]));
+                self.push_interior(prims, bbox, axis, n / 2, depth);
+            }
+            BVHSplit::SAH => {
+                let mut centroid_box = AABB::new(&prims[0].centroid, &prims[0].centroid);
+                for p in prims.iter().skip(1) {
+                    centroid_box =
+                        surrounding_box(&centroid_box, &AABB::new(&p.centroid, &p.centroid));
+                }
+                match find_sah_split(prims, &bbox, &centroid_box) {
+                    Some((_, _, cost)) if n <= self.options.max_leaf_size && cost >= n as f32 => {
+                        self.push_leaf(prims, bbox, depth)
+                    }
+                    Some((axis, split, _)) => {
+                        let extent = centroid_box.max[axis] - centroid_box.min[axis];
+                        let min = centroid_box.min[axis];
+                        let mid =
+                            partition(prims, |p| bin_index(p.centroid[axis], min, extent) <= split);
+                        self.push_interior(prims, bbox, axis, mid, depth);
+                    }
+                    // All centroids coincide, so no bin split exists.
+                    None if n <= self.options.max_leaf_size => self.push_leaf(prims, bbox, depth),
+                    None => self.push_interior(prims, bbox, 0, n / 2, depth),
+                }
+            }
         }
     }
 }
 
-impl Hittable for BVHNode {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if self.bbox.hit(r, t_min, t_max) {
-            match self.left.hit(r, t_min, t_max) {
-                Some(left_rec) => match self.right.hit(r, t_min, left_rec.t) {
-                    Some(right_rec) => Some(right_rec),
-                    None => Some(left_rec),
-                },
-                None => self.right.hit(r, t_min, t_max),
+impl BVH {
+    /// Builds a BVH over `list`, which must not be empty, and reports how the tree came out.
+    pub fn new(
+        list: &[Arc<dyn Hittable>],
+        time0: f32,
+        time1: f32,
+        options: &BVHOptions,
+    ) -> (Self, BVHStats) {
+        let start = Instant::now();
+        let mut prims: Vec<Primitive> = list
+            .iter()
+            .map(|shape| {
+                let bbox = shape.bounding_box(time0, time1).unwrap();
+                Primitive {
+                    shape: shape.clone(),
+                    bbox: bbox,
+                    centroid: 0.5 * (bbox.min + bbox.max),
+                }
+            })
+            .collect();
+        let options = BVHOptions {
+            split: options.split,
+            max_leaf_size: options.max_leaf_size.max(1),
+        };
+        let mut builder = Builder {
+            options: &options,
+            rng: Pcg32::new(prims.len() as u64, 0),
+            nodes: Vec::with_capacity(2 * prims.len()),
+            primitives: Vec::with_capacity(prims.len()),
+            stats: BVHStats::default(),
+        };
+        builder.build(&mut prims, 0);
+        let mut stats = builder.stats;
+        stats.build_time = start.elapsed();
+        let bvh = Self {
+            nodes: builder.nodes,
+            primitives: builder.primitives,
+        };
+        (bvh, stats)
+    }
+}
+
+impl Hittable for BVH {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        let inv_direction = Vec3::new(
+            1.0 / r.direction.0,
+            1.0 / r.direction.1,
+            1.0 / r.direction.2,
+        );
+        let negative = [
+            inv_direction.0 < 0.0,
+            inv_direction.1 < 0.0,
+            inv_direction.2 < 0.0,
+        ];
+        let mut closest = t_max;
+        let mut result = None;
+
+        let mut stack = [0_u32; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut index = 0;
+        let mut n_visits = 0;
+        let mut n_tests = 0;
+        loop {
+            let node = &self.nodes[index];
+            n_visits += 1;
+            if node
+                .bbox
+                .hit_inverse(&r.origin, &inv_direction, t_min, closest)
+            {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    n_tests += node.count as u64;
+                    for shape in &self.primitives[first..first + node.count as usize] {
+                        if let Some(rec) = shape.hit(r, t_min, closest, sampler) {
+                            closest = rec.t;
+                            result = Some(rec);
+                        }
+                    }
+                } else {
+                    // Visit the child on the near side of the split first, so
+                    // a hit there can cull the far one.
+                    if negative[node.axis as usize] {
+                        stack[stack_size] = index as u32 + 1;
+                        index = node.offset as usize;
+                    } else {
+                        stack[stack_size] = node.offset;
+                        index += 1;
+                    }
+                    stack_size += 1;
+                    continue;
+                }
             }
-        } else {
-            None
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            index = stack[stack_size] as usize;
         }
+        stats::record(|c| {
+            c.bvh_node_visits += n_visits;
+            c.primitive_tests += n_tests;
+        });
+        result
     }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
-        Some(self.bbox)
+        Some(self.nodes[0].bbox)
     }
 }
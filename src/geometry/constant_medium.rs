@@ -4,6 +4,7 @@ use crate::materials::Isotropic;
 use crate::textures::Texture;
 
 use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
@@ -29,9 +30,9 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        match self.boundary.hit(r, std::f32::MIN, std::f32::MAX) {
-            Some(mut rec1) => match self.boundary.hit(r, rec1.t + 0.0001, std::f32::MAX) {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        match self.boundary.hit(r, f32::MIN, f32::MAX, sampler) {
+            Some(mut rec1) => match self.boundary.hit(r, rec1.t + 0.0001, f32::MAX, sampler) {
                 Some(mut rec2) => {
                     if rec1.t < t_min {
                         rec1.t = t_min;
@@ -46,7 +47,8 @@ impl Hittable for ConstantMedium {
                         rec1.t = 0.0;
                     }
                     let distance_inside_boundary = (rec2.t - rec1.t) * r.direction.length();
-                    let hit_distance = -(1.0 / self.density) * rand::random::<f32>().ln();
+                    let u = sampler.get_1d();
+                    let hit_distance = -(1.0 / self.density) * (1.0 - u).ln();
                     if hit_distance < distance_inside_boundary {
                         let t = rec1.t + hit_distance / r.direction.length();
                         Some(HitRecord::new(
@@ -1,6 +1,7 @@
 use super::aabb::AABB;
-use super::hittable::{HitRecord, Hittable};
-use crate::linalg::Ray;
+use super::hittable::{HitRecord, Hittable, LightSample};
+use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
@@ -16,8 +17,8 @@ impl FlipNormals {
 }
 
 impl Hittable for FlipNormals {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        match self.ptr.hit(r, t_min, t_max) {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        match self.ptr.hit(r, t_min, t_max, sampler) {
             Some(mut rec) => {
                 rec.normal = -1.0 * rec.normal;
                 Some(rec)
@@ -28,4 +29,16 @@ impl Hittable for FlipNormals {
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         self.ptr.bounding_box(t0, t1)
     }
+
+    fn is_light(&self) -> bool {
+        self.ptr.is_light()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        self.ptr.sample_towards(origin, sampler)
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        self.ptr.pdf_towards(origin, direction)
+    }
 }
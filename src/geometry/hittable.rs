@@ -1,9 +1,11 @@
 use super::aabb::AABB;
 use crate::linalg::{Ray, Vec3};
 use crate::materials::Material;
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
+/// Where and how a ray hit a surface.
 pub struct HitRecord {
     pub t: f32,
     pub u: f32,
@@ -26,7 +28,31 @@ impl HitRecord {
     }
 }
 
+/// A point picked on a light, with the density of its direction seen from
+/// where it was picked, per unit solid angle.
+pub struct LightSample {
+    pub point: Vec3,
+    pub pdf: f32,
+}
+
+/// Anything a ray can intersect.
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    /// The closest intersection with `r` for `t` in `(t_min, t_max)`. Shapes a ray
+    /// meets at random, such as participating media, draw where from `sampler`.
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord>;
+    /// A box enclosing the object over the shutter interval `[t0, t1]`, `None` if unbounded.
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
+
+    /// Whether the object emits light and can be sampled as a light.
+    fn is_light(&self) -> bool {
+        false
+    }
+    /// Picks a point on the object as seen from `origin`, `None` if it can't.
+    fn sample_towards(&self, _origin: &Vec3, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        None
+    }
+    /// The density `sample_towards` gives to `direction` from `origin`.
+    fn pdf_towards(&self, _origin: &Vec3, _direction: &Vec3) -> f32 {
+        0.0
+    }
 }
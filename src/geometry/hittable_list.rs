@@ -1,6 +1,7 @@
 use super::aabb::{surrounding_box, AABB};
 use super::hittable::{HitRecord, Hittable};
 use crate::linalg::Ray;
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
@@ -24,18 +25,21 @@ impl HittableList {
     }
 }
 
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let mut closest_so_far = t_max;
         let mut rec: Option<HitRecord> = None;
 
         for hitable in self.list.iter() {
-            match hitable.hit(r, t_min, closest_so_far) {
-                Some(x) => {
-                    closest_so_far = x.t;
-                    rec = Some(x);
-                }
-                None => {}
+            if let Some(x) = hitable.hit(r, t_min, closest_so_far, sampler) {
+                closest_so_far = x.t;
+                rec = Some(x);
             }
         }
 
@@ -43,7 +47,7 @@ impl Hittable for HittableList {
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
-        if self.list.len() < 1 {
+        if self.list.is_empty() {
             return None;
         }
         let mut _box: AABB;
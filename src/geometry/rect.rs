@@ -1,10 +1,40 @@
 use super::aabb::AABB;
-use super::hittable::{HitRecord, Hittable};
+use super::hittable::{HitRecord, Hittable, LightSample};
 use crate::linalg::{Ray, Vec3};
 use crate::materials::Material;
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
+/// Density per unit solid angle, seen from `origin`, of picking `point`
+/// uniformly on a flat shape of the given area, such as a rectangle.
+pub(super) fn area_pdf(origin: &Vec3, point: &Vec3, normal: &Vec3, area: f32) -> f32 {
+    let to_point = *point - *origin;
+    let distance_squared = to_point.squared_length();
+    let cosine = Vec3::dot(normal, &to_point).abs() / distance_squared.sqrt();
+    if cosine < 1e-6 || area <= 0.0 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+pub(super) fn sample_area(
+    origin: &Vec3,
+    point: Vec3,
+    normal: &Vec3,
+    area: f32,
+) -> Option<LightSample> {
+    let pdf = area_pdf(origin, &point, normal, area);
+    if pdf > 0.0 {
+        Some(LightSample {
+            point: point,
+            pdf: pdf,
+        })
+    } else {
+        None
+    }
+}
+
 pub struct XYRect {
     x0: f32,
     y0: f32,
@@ -26,10 +56,14 @@ impl XYRect {
             material: material,
         }
     }
-}
 
-impl Hittable for XYRect {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn area(&self) -> f32 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    /// The closest intersection with `r` for `t` in `(t_min, t_max)`, which needs no
+    /// sampler; light sampling uses it too.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let t = (self.k - r.origin.2) / r.direction.2;
         if t < t_min || t > t_max {
             return None;
@@ -51,6 +85,18 @@ impl Hittable for XYRect {
             self.material.clone(),
         ))
     }
+}
+
+impl Hittable for XYRect {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max)
+    }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(
@@ -58,6 +104,29 @@ impl Hittable for XYRect {
             &Vec3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emitter()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (s, t) = sampler.get_2d();
+        let a = self.x0 + s * (self.x1 - self.x0);
+        let b = self.y0 + t * (self.y1 - self.y0);
+        sample_area(
+            origin,
+            Vec3::new(a, b, self.k),
+            &Vec3::new(0.0, 0.0, 1.0),
+            self.area(),
+        )
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        match self.intersect(&Ray::new(*origin, *direction, 0.0), 0.001, f32::MAX) {
+            Some(rec) => area_pdf(origin, &rec.p, &rec.normal, self.area()),
+            None => 0.0,
+        }
+    }
 }
 
 pub struct XZRect {
@@ -80,10 +149,14 @@ impl XZRect {
             material: material,
         }
     }
-}
 
-impl Hittable for XZRect {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn area(&self) -> f32 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    /// The closest intersection with `r` for `t` in `(t_min, t_max)`, which needs no
+    /// sampler; light sampling uses it too.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let t = (self.k - r.origin.1) / r.direction.1;
         if t < t_min || t > t_max {
             return None;
@@ -105,6 +178,18 @@ impl Hittable for XZRect {
             self.material.clone(),
         ))
     }
+}
+
+impl Hittable for XZRect {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max)
+    }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(
@@ -112,6 +197,29 @@ impl Hittable for XZRect {
             &Vec3::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emitter()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (s, t) = sampler.get_2d();
+        let a = self.x0 + s * (self.x1 - self.x0);
+        let b = self.z0 + t * (self.z1 - self.z0);
+        sample_area(
+            origin,
+            Vec3::new(a, self.k, b),
+            &Vec3::new(0.0, 1.0, 0.0),
+            self.area(),
+        )
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        match self.intersect(&Ray::new(*origin, *direction, 0.0), 0.001, f32::MAX) {
+            Some(rec) => area_pdf(origin, &rec.p, &rec.normal, self.area()),
+            None => 0.0,
+        }
+    }
 }
 
 pub struct YZRect {
@@ -134,10 +242,14 @@ impl YZRect {
             material: material,
         }
     }
-}
 
-impl Hittable for YZRect {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn area(&self) -> f32 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    /// The closest intersection with `r` for `t` in `(t_min, t_max)`, which needs no
+    /// sampler; light sampling uses it too.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let t = (self.k - r.origin.0) / r.direction.0;
         if t < t_min || t > t_max {
             return None;
@@ -159,6 +271,18 @@ impl Hittable for YZRect {
             self.material.clone(),
         ))
     }
+}
+
+impl Hittable for YZRect {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max)
+    }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(
@@ -166,4 +290,27 @@ impl Hittable for YZRect {
             &Vec3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emitter()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (s, t) = sampler.get_2d();
+        let a = self.y0 + s * (self.y1 - self.y0);
+        let b = self.z0 + t * (self.z1 - self.z0);
+        sample_area(
+            origin,
+            Vec3::new(self.k, a, b),
+            &Vec3::new(1.0, 0.0, 0.0),
+            self.area(),
+        )
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        match self.intersect(&Ray::new(*origin, *direction, 0.0), 0.001, f32::MAX) {
+            Some(rec) => area_pdf(origin, &rec.p, &rec.normal, self.area()),
+            None => 0.0,
+        }
+    }
 }
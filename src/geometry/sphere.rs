@@ -1,7 +1,9 @@
 use super::aabb::{surrounding_box, AABB};
-use super::hittable::{HitRecord, Hittable};
-use crate::linalg::{Ray, Vec3};
+use super::hittable::{HitRecord, Hittable, LightSample};
+use crate::linalg::{Ray, Vec3, ONB};
 use crate::materials::Material;
+use crate::random::sampler::Sampler;
+use crate::random::utils::{cone_pdf, random_in_cone};
 
 use std::sync::Arc;
 
@@ -19,20 +21,22 @@ impl Sphere {
             material: material,
         }
     }
-}
 
-fn get_sphere_uv(p: &Vec3) -> (f32, f32) {
-    let phi = p.2.atan2(p.0);
-    let theta = p.1.asin();
-    (
-        1.0 - 0.5 * (phi + std::f32::consts::PI) * std::f32::consts::FRAC_1_PI,
-        (theta + std::f32::consts::FRAC_PI_2) * std::f32::consts::FRAC_1_PI,
-    )
-}
+    /// Cosine of the half-angle of the cone the sphere covers from `origin`,
+    /// `None` from inside the sphere.
+    fn cos_theta_max(&self, origin: &Vec3) -> Option<f32> {
+        let distance_squared = (self.center - *origin).squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        Some((1.0 - radius_squared / distance_squared).sqrt())
+    }
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = &r.origin - &self.center;
+    /// The closest intersection with `r` for `t` in `(t_min, t_max)`, which needs no
+    /// sampler; light sampling uses it too.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let oc = r.origin - self.center;
         let a = Vec3::dot(&r.direction, &r.direction);
         let b = Vec3::dot(&oc, &r.direction);
         let c = Vec3::dot(&oc, &oc) - self.radius * self.radius;
@@ -70,6 +74,27 @@ impl Hittable for Sphere {
         }
         None
     }
+}
+
+fn get_sphere_uv(p: &Vec3) -> (f32, f32) {
+    let phi = p.2.atan2(p.0);
+    let theta = p.1.asin();
+    (
+        1.0 - 0.5 * (phi + std::f32::consts::PI) * std::f32::consts::FRAC_1_PI,
+        (theta + std::f32::consts::FRAC_PI_2) * std::f32::consts::FRAC_1_PI,
+    )
+}
+
+impl Hittable for Sphere {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max)
+    }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(
@@ -77,6 +102,40 @@ impl Hittable for Sphere {
             &(self.center + Vec3::new(self.radius, self.radius, self.radius)),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emitter()
+    }
+
+    // Samples the cone of directions the sphere covers, uniformly in solid angle.
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let cos_theta_max = self.cos_theta_max(origin)?;
+        let uvw = ONB::from_w(&(self.center - *origin));
+        let direction = uvw.local(&random_in_cone(sampler, cos_theta_max));
+        let r = Ray::new(*origin, direction, 0.0);
+        // The ray misses only by rounding at the very rim of the cone.
+        let t = match self.intersect(&r, 0.0, f32::MAX) {
+            Some(rec) => rec.t,
+            None => Vec3::dot(&(self.center - *origin), &direction),
+        };
+        Some(LightSample {
+            point: r.point_at_parameter(t),
+            pdf: cone_pdf(cos_theta_max),
+        })
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max)
+                if self
+                    .intersect(&Ray::new(*origin, *direction, 0.0), 0.001, f32::MAX)
+                    .is_some() =>
+            {
+                cone_pdf(cos_theta_max)
+            }
+            _ => 0.0,
+        }
+    }
 }
 
 pub struct MovingSphere {
@@ -116,8 +175,14 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = &r.origin - &self.center(r.time);
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        let oc = r.origin - self.center(r.time);
         let a = Vec3::dot(&r.direction, &r.direction);
         let b = Vec3::dot(&oc, &r.direction);
         let c = Vec3::dot(&oc, &oc) - self.radius * self.radius;
@@ -1,6 +1,7 @@
 use super::aabb::AABB;
-use super::hittable::{HitRecord, Hittable};
-use crate::linalg::{Ray, Vec3};
+use super::hittable::{HitRecord, Hittable, LightSample};
+use crate::linalg::{Mat4, Ray, Vec3};
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
@@ -20,9 +21,9 @@ impl Translation {
 }
 
 impl Hittable for Translation {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let moved = Ray::new(r.origin - self.offset, r.direction, r.time);
-        match self.hitable.hit(&moved, t_min, t_max) {
+        match self.hitable.hit(&moved, t_min, t_max, sampler) {
             Some(mut rec) => {
                 rec.p += self.offset;
                 Some(rec)
@@ -32,13 +33,27 @@ impl Hittable for Translation {
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
-        match self.hitable.bounding_box(t0, t1) {
-            Some(bbox) => Some(AABB::new(
-                &(bbox.min + self.offset),
-                &(bbox.max + self.offset),
-            )),
-            None => None,
-        }
+        self.hitable
+            .bounding_box(t0, t1)
+            .map(|bbox| AABB::new(&(bbox.min + self.offset), &(bbox.max + self.offset)))
+    }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        self.hitable
+            .sample_towards(&(*origin - self.offset), sampler)
+            .map(|sample| LightSample {
+                point: sample.point + self.offset,
+                pdf: sample.pdf,
+            })
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        self.hitable
+            .pdf_towards(&(*origin - self.offset), direction)
     }
 }
 
@@ -57,8 +72,8 @@ impl YRotation {
         let cos_theta = radians.cos();
         let bbox = hitable.bounding_box(0.0, 1.0).unwrap();
 
-        let mut min = Vec3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX);
-        let mut max = Vec3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN);
+        let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
 
         for i in 0..2 {
             for j in 0..2 {
@@ -103,10 +118,28 @@ impl YRotation {
     }
 }
 
+impl YRotation {
+    fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.0 - self.sin_theta * v.2,
+            v.1,
+            self.sin_theta * v.0 + self.cos_theta * v.2,
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.0 + self.sin_theta * v.2,
+            v.1,
+            -self.sin_theta * v.0 + self.cos_theta * v.2,
+        )
+    }
+}
+
 impl Hittable for YRotation {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut origin = r.origin.clone();
-        let mut direction = r.direction.clone();
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        let mut origin = r.origin;
+        let mut direction = r.direction;
         origin.0 = self.cos_theta * r.origin.0 - self.sin_theta * r.origin.2;
         origin.2 = self.sin_theta * r.origin.0 + self.cos_theta * r.origin.2;
 
@@ -115,7 +148,7 @@ impl Hittable for YRotation {
 
         let rotated_ray = Ray::new(origin, direction, r.time);
 
-        match self.hitable.hit(&rotated_ray, t_min, t_max) {
+        match self.hitable.hit(&rotated_ray, t_min, t_max, sampler) {
             Some(mut rec) => {
                 let mut p = rec.p;
                 let mut normal = rec.normal;
@@ -135,4 +168,110 @@ impl Hittable for YRotation {
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(self.bbox)
     }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    // A rotation keeps solid angles, so the densities carry over unchanged.
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        self.hitable
+            .sample_towards(&self.to_local(origin), sampler)
+            .map(|sample| LightSample {
+                point: self.to_world(&sample.point),
+                pdf: sample.pdf,
+            })
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        self.hitable
+            .pdf_towards(&self.to_local(origin), &self.to_local(direction))
+    }
+}
+
+/// An arbitrary affine transform of a shape, given as an object-to-world matrix.
+pub struct Transform {
+    hitable: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    normal_matrix: Mat4,
+    volume_scale: f32,
+}
+
+impl Transform {
+    /// Panics if `matrix` is not invertible.
+    pub fn new(hitable: Arc<dyn Hittable>, matrix: Mat4) -> Self {
+        let inverse = matrix
+            .inverse()
+            .expect("transform matrix is not invertible");
+        Self {
+            hitable: hitable,
+            matrix: matrix,
+            inverse: inverse,
+            normal_matrix: inverse.transpose(),
+            volume_scale: matrix.linear_determinant().abs(),
+        }
+    }
+
+    /// How much denser a direction gets per unit solid angle going from the
+    /// shape's space to the world, for the unit direction `local` in the former.
+    fn solid_angle_scale(&self, local: &Vec3) -> f32 {
+        self.matrix.transform_vector(local).length().powi(3) / self.volume_scale
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        // The direction is not renormalised, so t means the same in both spaces.
+        let local = Ray::new(
+            self.inverse.transform_point(&r.origin),
+            self.inverse.transform_vector(&r.direction),
+            r.time,
+        );
+        match self.hitable.hit(&local, t_min, t_max, sampler) {
+            Some(mut rec) => {
+                rec.p = self.matrix.transform_point(&rec.p);
+                rec.normal = Vec3::unit(&self.normal_matrix.transform_vector(&rec.normal));
+                Some(rec)
+            }
+            None => None,
+        }
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let bbox = self.hitable.bounding_box(t0, t1)?;
+        let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { bbox.min.0 } else { bbox.max.0 },
+                if i & 2 == 0 { bbox.min.1 } else { bbox.max.1 },
+                if i & 4 == 0 { bbox.min.2 } else { bbox.max.2 },
+            );
+            let p = self.matrix.transform_point(&corner);
+            min = Vec3::new(min.0.min(p.0), min.1.min(p.1), min.2.min(p.2));
+            max = Vec3::new(max.0.max(p.0), max.1.max(p.1), max.2.max(p.2));
+        }
+        Some(AABB::new(&min, &max))
+    }
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let local_origin = self.inverse.transform_point(origin);
+        let sample = self.hitable.sample_towards(&local_origin, sampler)?;
+        let local = Vec3::unit(&(sample.point - local_origin));
+        Some(LightSample {
+            point: self.matrix.transform_point(&sample.point),
+            pdf: sample.pdf * self.solid_angle_scale(&local),
+        })
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        let local = Vec3::unit(&self.inverse.transform_vector(direction));
+        self.hitable
+            .pdf_towards(&self.inverse.transform_point(origin), &local)
+            * self.solid_angle_scale(&local)
+    }
 }
@@ -1,7 +1,9 @@
 use super::aabb::AABB;
-use super::hittable::{HitRecord, Hittable};
+use super::hittable::{HitRecord, Hittable, LightSample};
+use super::rect::{area_pdf, sample_area};
 use crate::linalg::{Ray, Vec3};
 use crate::materials::Material;
+use crate::random::sampler::Sampler;
 
 use std::sync::Arc;
 
@@ -33,28 +35,19 @@ impl Triangle {
             material: material,
         }
     }
-}
 
-#[inline(always)]
-fn fmax(a: f32, b: f32) -> f32 {
-    if a > b {
-        a
-    } else {
-        b
+    fn vertex(&self, i: usize) -> Vec3 {
+        self.vertices[self.v_index[i]]
     }
-}
 
-#[inline(always)]
-fn fmin(a: f32, b: f32) -> f32 {
-    if a < b {
-        a
-    } else {
-        b
+    fn edges(&self) -> (Vec3, Vec3) {
+        let v0 = self.vertex(0);
+        (self.vertex(1) - v0, self.vertex(2) - v0)
     }
-}
 
-impl Hittable for Triangle {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    /// The closest intersection with `r` for `t` in `(t_min, t_max)`, which needs no
+    /// sampler; light sampling uses it too.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let e1 = self.vertices[self.v_index[1]] - self.vertices[self.v_index[0]];
         let e2 = self.vertices[self.v_index[2]] - self.vertices[self.v_index[0]];
         let q = Vec3::cross(&r.direction, &e2);
@@ -92,6 +85,36 @@ impl Hittable for Triangle {
             Some(HitRecord::new(t, u, v, p, normal, self.material.clone()))
         }
     }
+}
+
+#[inline(always)]
+fn fmax(a: f32, b: f32) -> f32 {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+#[inline(always)]
+fn fmin(a: f32, b: f32) -> f32 {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max)
+    }
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         let min = Vec3::new(
             fmin(
@@ -142,6 +165,38 @@ impl Hittable for Triangle {
         );
         Some(AABB::new(&min, &max))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emitter()
+    }
+
+    fn sample_towards(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (e1, e2) = self.edges();
+        // Folding the far half of the parallelogram back keeps the point uniform.
+        let (mut s, mut t) = sampler.get_2d();
+        if s + t > 1.0 {
+            s = 1.0 - s;
+            t = 1.0 - t;
+        }
+        let normal = Vec3::cross(&e1, &e2);
+        sample_area(
+            origin,
+            self.vertex(0) + s * e1 + t * e2,
+            &Vec3::unit(&normal),
+            0.5 * normal.length(),
+        )
+    }
+
+    fn pdf_towards(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        match self.intersect(&Ray::new(*origin, *direction, 0.0), 0.001, f32::MAX) {
+            Some(rec) => {
+                let (e1, e2) = self.edges();
+                let normal = Vec3::cross(&e1, &e2);
+                area_pdf(origin, &rec.p, &Vec3::unit(&normal), 0.5 * normal.length())
+            }
+            None => 0.0,
+        }
+    }
 }
 
 pub struct TriangleMesh {
@@ -219,25 +274,3 @@ impl<'a> Iterator for TriangleMeshIterator<'a> {
         }
     }
 }
-
-// {
-//             "type": "triangle",
-//             "vertices": [
-//                 [
-//                     5.0,
-//                     0.0,
-//                     1.0
-//                 ],
-//                 [
-//                     5.0,
-//                     0.0,
-//                     2.0
-//                 ],
-//                 [
-//                     5.5,
-//                     1.0,
-//                     1.0
-//                 ]
-//             ],
-//             "material": "red_metalic"
-//         },
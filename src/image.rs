@@ -1,3 +1,5 @@
+use crate::linalg::Vec3;
+
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufWriter;
@@ -5,65 +7,1057 @@ use std::path::Path;
 
 use png::HasParameters;
 
-#[allow(dead_code)]
-pub fn write_to_png(path_to_file: &str, data: &mut [u8], width: u32, height: u32) {
-    let path = Path::new(path_to_file);
-    let display = path.display();
+/// Writes 8-bit RGB data, row by row from the top, as a PNG.
+pub fn write_to_png(
+    path_to_file: &str,
+    data: &[u8],
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    let w = create(path_to_file)?;
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(data));
+    match result {
+        Err(why) => Err(format!("couldn't write {}: {}", path_to_file, why)),
+        Ok(()) => Ok(()),
+    }
+}
 
-    let file = match File::create(&path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why.to_string()),
-        Ok(file) => file,
-    };
+/// Writes 8-bit RGB data, row by row from the top, as a binary (P6) PPM.
+pub fn write_to_ppm(
+    path_to_file: &str,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let mut bytes = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    bytes.extend_from_slice(&data[..width * height * 3]);
+    write_all(path_to_file, &bytes)
+}
 
-    let ref mut w = BufWriter::new(file);
+/// Writes 8-bit RGB data, row by row from the top, as an ASCII (P3) PPM.
+pub fn write_to_ppm_ascii(
+    path_to_file: &str,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let mut text = format!("P3\n{} {}\n255\n", width, height);
+    for row in data.chunks(width * 3).take(height) {
+        let values: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+        // Lines of a plain PPM should stay under 70 characters.
+        for line in values.chunks(15) {
+            text.push_str(&line.join(" "));
+            text.push('\n');
+        }
+    }
+    write_all(path_to_file, text.as_bytes())
+}
 
-    let mut encoder = png::Encoder::new(w, width, height);
-    encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(&data).unwrap();
+/// The samples of a decoded image.
+pub enum Pixels {
+    /// Bytes, 255 being full intensity.
+    U8(Vec<u8>),
+    /// Floats, as stored in float files; other samples above 8 bits are scaled to
+    /// `[0, 1]`.
+    F32(Vec<f32>),
 }
 
-#[allow(dead_code)]
-pub fn write_to_ppm(path_to_file: &str, data: &mut [u8], width: u32, height: u32) {
-    let path = Path::new(path_to_file);
-    let display = path.display();
+/// A decoded image, stored row by row from the top-left corner.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Samples per pixel: gray, gray and alpha, RGB or RGBA.
+    pub channels: usize,
+    pub pixels: Pixels,
+}
 
-    let mut file = match File::create(&path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why.to_string()),
-        Ok(file) => file,
-    };
+impl Image {
+    /// Channel `c` of the pixel at `(x, y)`, bytes scaled to `[0, 1]`.
+    pub fn sample(&self, x: usize, y: usize, c: usize) -> f32 {
+        let i = (y * self.width + x) * self.channels + c;
+        match &self.pixels {
+            Pixels::U8(data) => data[i] as f32 / 255.0,
+            Pixels::F32(data) => data[i],
+        }
+    }
 
-    write!(file, "P3\n{} {}\n255\n", width, height).unwrap();
-    for x in 0..height {
-        for y in 0..width {
-            write!(
-                file,
-                "{} {} {} ",
-                data[(x * width + y * 3) as usize],
-                data[(x * width + y * 3 + 1) as usize],
-                data[(x * width + y * 3 + 2) as usize]
+    /// The color of the pixel at `(x, y)`; gray images repeat their one channel.
+    pub fn rgb(&self, x: usize, y: usize) -> Vec3 {
+        if self.channels < 3 {
+            let gray = self.sample(x, y, 0);
+            Vec3::new(gray, gray, gray)
+        } else {
+            Vec3::new(
+                self.sample(x, y, 0),
+                self.sample(x, y, 1),
+                self.sample(x, y, 2),
             )
-            .unwrap();
         }
-        write!(file, "\n").unwrap();
+    }
+
+    /// The image with its 8-bit colour samples decoded from sRGB to linear light,
+    /// for images that give colours to shade with. Alpha, floats and samples of
+    /// other depths are kept as they are.
+    pub fn into_linear(self) -> Image {
+        let data = match self.pixels {
+            Pixels::U8(data) => data,
+            pixels => {
+                return Image {
+                    pixels: pixels,
+                    ..self
+                }
+            }
+        };
+        let table: Vec<f32> = (0..256).map(|x| srgb_to_linear(x as f32 / 255.0)).collect();
+        let has_alpha = self.channels == 2 || self.channels == 4;
+        let linear = data
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                if has_alpha && i % self.channels == self.channels - 1 {
+                    x as f32 / 255.0
+                } else {
+                    table[x as usize]
+                }
+            })
+            .collect();
+        Image {
+            pixels: Pixels::F32(linear),
+            ..self
+        }
+    }
+
+    /// The whole image as RGB floats, without alpha.
+    pub fn to_rgb(&self) -> Vec<f32> {
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = self.rgb(x, y);
+                rgb.extend_from_slice(&[color.0, color.1, color.2]);
+            }
+        }
+        rgb
+    }
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Reads an image, picking the decoder from the file extension.
+pub fn read_image(path_to_file: &str) -> Result<Image, String> {
+    let extension = Path::new(path_to_file)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_deref() {
+        Some("png") => read_png(path_to_file),
+        Some("hdr") => read_hdr(path_to_file),
+        Some("ppm") | Some("pgm") | Some("pnm") => read_ppm(path_to_file),
+        Some("pfm") => read_pfm(path_to_file),
+        Some("tga") => read_tga(path_to_file),
+        Some("bmp") => read_bmp(path_to_file),
+        _ => Err(format!(
+            "couldn't open {}: unknown image type (expected png, hdr, ppm, pgm, pfm, tga or bmp)",
+            path_to_file
+        )),
+    }
+}
+
+fn read_all(path_to_file: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    match File::open(Path::new(path_to_file)) {
+        Err(why) => Err(format!("couldn't open {}: {}", path_to_file, why)),
+        Ok(mut file) => match file.read_to_end(&mut bytes) {
+            Err(why) => Err(format!("couldn't read {}: {}", path_to_file, why)),
+            Ok(_) => Ok(bytes),
+        },
     }
 }
 
-#[allow(dead_code)]
-pub fn read_png(path_to_file: &str) -> (Vec<u8>, usize, usize) {
-    let path = Path::new(path_to_file);
-    let display = path.display();
+/// Reads a PNG; 16-bit samples are kept as floats in `[0, 1]`.
+pub fn read_png(path_to_file: &str) -> Result<Image, String> {
+    let bytes = read_all(path_to_file)?;
+    let display = Path::new(path_to_file).display();
 
-    let file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", display, why.to_string()),
-        Ok(file) => file,
+    // The bit depth is the first byte after the width and height in the header.
+    let sixteen_bit = bytes.get(24) == Some(&16);
+    // The default transformations expand palettes and strip 16-bit samples to 8 bits,
+    // and 16-bit samples can't be kept without turning every transformation off.
+    let mut decoder = png::Decoder::new(&bytes[..]);
+    if sixteen_bit {
+        decoder.set(png::Transformations::IDENTITY);
+    }
+    let (info, mut reader) = match decoder.read_info() {
+        Err(why) => return Err(format!("couldn't decode {}: {}", display, why)),
+        Ok(x) => x,
     };
-    let decoder = png::Decoder::new(file);
-    let (info, mut reader) = decoder.read_info().unwrap();
     // Allocate the output buffer.
     let mut buf = vec![0; info.buffer_size()];
     // Read the next frame. Currently this function should only called once.
-    // The default options
-    reader.next_frame(&mut buf).unwrap();
-    (buf, info.width as usize, info.height as usize)
+    if let Err(why) = reader.next_frame(&mut buf) {
+        return Err(format!("couldn't decode {}: {}", display, why));
+    }
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let sample_size = if sixteen_bit { 2 } else { 1 };
+    let pixels = if sixteen_bit {
+        // PNG stores 16-bit samples big-endian.
+        Pixels::F32(
+            buf.chunks_exact(2)
+                .map(|x| u16::from_be_bytes([x[0], x[1]]) as f32 / 65535.0)
+                .collect(),
+        )
+    } else {
+        Pixels::U8(buf)
+    };
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: info.line_size / width / sample_size,
+        pixels: pixels,
+    })
+}
+
+/// Reads a Radiance RGBE (.hdr) image as linear RGB floats.
+pub fn read_hdr(path_to_file: &str) -> Result<Image, String> {
+    let bytes = read_all(path_to_file)?;
+    let invalid = |reason: &str| format!("couldn't decode {}: {}", path_to_file, reason);
+
+    // The header is text up to an empty line, followed by the resolution line.
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> Option<String> {
+        let start = *pos;
+        let end = start + bytes[start..].iter().position(|&b| b == b'\n')?;
+        *pos = end + 1;
+        Some(String::from_utf8_lossy(&bytes[start..end]).into_owned())
+    };
+    match next_line(&mut pos) {
+        Some(ref magic) if magic.starts_with("#?") => {}
+        _ => return Err(invalid("not a Radiance HDR file")),
+    }
+    loop {
+        match next_line(&mut pos) {
+            None => return Err(invalid("truncated header")),
+            Some(line) if line.is_empty() => break,
+            Some(line) => {
+                if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                    return Err(invalid(&format!("unsupported {}", line)));
+                }
+            }
+        }
+    }
+    let resolution = match next_line(&mut pos) {
+        Some(line) => line,
+        None => return Err(invalid("missing resolution")),
+    };
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match fields.as_slice() {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+            _ => return Err(invalid("bad resolution")),
+        },
+        _ => return Err(invalid("only -Y H +X W images are supported")),
+    };
+
+    let mut rgbe = vec![0_u8; width * 4];
+    let mut data = Vec::with_capacity(width * height * 3);
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width)
+            && bytes.len() >= pos + 4
+            && bytes[pos] == 2
+            && bytes[pos + 1] == 2
+            && bytes[pos + 2] & 0x80 == 0;
+        if rle {
+            if ((bytes[pos + 2] as usize) << 8 | bytes[pos + 3] as usize) != width {
+                return Err(invalid("scanline width mismatch"));
+            }
+            pos += 4;
+            // Each of the four components is run-length encoded on its own.
+            for c in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = match bytes.get(pos) {
+                        Some(&b) => b as usize,
+                        None => return Err(invalid("truncated data")),
+                    };
+                    pos += 1;
+                    if count > 128 {
+                        let run = count - 128;
+                        if x + run > width || pos >= bytes.len() {
+                            return Err(invalid("bad run length"));
+                        }
+                        for i in 0..run {
+                            rgbe[(x + i) * 4 + c] = bytes[pos];
+                        }
+                        pos += 1;
+                        x += run;
+                    } else {
+                        if count == 0 || x + count > width || pos + count > bytes.len() {
+                            return Err(invalid("bad run length"));
+                        }
+                        for i in 0..count {
+                            rgbe[(x + i) * 4 + c] = bytes[pos + i];
+                        }
+                        pos += count;
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            if pos + width * 4 > bytes.len() {
+                return Err(invalid("truncated data"));
+            }
+            rgbe.copy_from_slice(&bytes[pos..pos + width * 4]);
+            pos += width * 4;
+        }
+        for px in rgbe.chunks(4) {
+            if px[3] == 0 {
+                data.extend_from_slice(&[0.0, 0.0, 0.0]);
+            } else {
+                let f = 2.0_f32.powi(px[3] as i32 - 136);
+                data.push((px[0] as f32 + 0.5) * f);
+                data.push((px[1] as f32 + 0.5) * f);
+                data.push((px[2] as f32 + 0.5) * f);
+            }
+        }
+    }
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: 3,
+        pixels: Pixels::F32(data),
+    })
+}
+
+/// Reads the next whitespace-separated token of a PNM or PFM header, skipping comments.
+fn header_token(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    loop {
+        while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if *pos < bytes.len() && bytes[*pos] == b'#' {
+            while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    let start = *pos;
+    while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+    if start == *pos {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+    }
+}
+
+/// Reads a gray (P2, P5) or RGB (P3, P6) netpbm image. Samples with a maximum
+/// other than 255 are scaled to floats in `[0, 1]`.
+pub fn read_ppm(path_to_file: &str) -> Result<Image, String> {
+    let bytes = read_all(path_to_file)?;
+    let invalid = |reason: &str| format!("couldn't decode {}: {}", path_to_file, reason);
+
+    let mut pos = 0;
+    let magic = header_token(&bytes, &mut pos);
+    let (channels, binary) = match magic.as_deref() {
+        Some("P2") => (1, false),
+        Some("P3") => (3, false),
+        Some("P5") => (1, true),
+        Some("P6") => (3, true),
+        _ => return Err(invalid("not a gray or RGB netpbm file")),
+    };
+    let number = |pos: &mut usize| -> Result<usize, String> {
+        match header_token(&bytes, pos).and_then(|t| t.parse::<usize>().ok()) {
+            Some(x) => Ok(x),
+            None => Err(invalid("bad header")),
+        }
+    };
+    let width = number(&mut pos)?;
+    let height = number(&mut pos)?;
+    let max = number(&mut pos)?;
+    if width == 0 || height == 0 || max == 0 || max > 65535 {
+        return Err(invalid("bad header"));
+    }
+
+    let n = width * height * channels;
+    let mut values = Vec::with_capacity(n);
+    if binary {
+        // A single whitespace character separates the header from the samples.
+        pos += 1;
+        let size = if max > 255 { 2 } else { 1 };
+        if bytes.len() < pos + n * size {
+            return Err(invalid("truncated data"));
+        }
+        for i in 0..n {
+            let at = pos + i * size;
+            values.push(if size == 2 {
+                (bytes[at] as usize) << 8 | bytes[at + 1] as usize
+            } else {
+                bytes[at] as usize
+            });
+        }
+    } else {
+        for _ in 0..n {
+            values.push(number(&mut pos)?);
+        }
+    }
+    if values.iter().any(|&x| x > max) {
+        return Err(invalid("sample above the maximum value"));
+    }
+
+    let pixels = if max == 255 {
+        Pixels::U8(values.iter().map(|&x| x as u8).collect())
+    } else {
+        Pixels::F32(values.iter().map(|&x| x as f32 / max as f32).collect())
+    };
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: channels,
+        pixels: pixels,
+    })
+}
+
+/// Reads a gray (Pf) or RGB (PF) portable float map.
+pub fn read_pfm(path_to_file: &str) -> Result<Image, String> {
+    let bytes = read_all(path_to_file)?;
+    let invalid = |reason: &str| format!("couldn't decode {}: {}", path_to_file, reason);
+
+    let mut pos = 0;
+    let channels = match header_token(&bytes, &mut pos).as_deref() {
+        Some("Pf") => 1,
+        Some("PF") => 3,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let width = header_token(&bytes, &mut pos).and_then(|t| t.parse::<usize>().ok());
+    let height = header_token(&bytes, &mut pos).and_then(|t| t.parse::<usize>().ok());
+    let scale = header_token(&bytes, &mut pos).and_then(|t| t.parse::<f32>().ok());
+    let (width, height, scale) = match (width, height, scale) {
+        (Some(w), Some(h), Some(s)) if w > 0 && h > 0 && s != 0.0 => (w, h, s),
+        _ => return Err(invalid("bad header")),
+    };
+    pos += 1;
+
+    let row_size = width * channels;
+    if bytes.len() < pos + row_size * height * 4 {
+        return Err(invalid("truncated data"));
+    }
+    // A negative scale means little-endian samples. Rows go from the bottom up.
+    let mut data = Vec::with_capacity(row_size * height);
+    for y in (0..height).rev() {
+        for i in 0..row_size {
+            let at = pos + (y * row_size + i) * 4;
+            let raw = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+            data.push(if scale < 0.0 {
+                f32::from_le_bytes(raw)
+            } else {
+                f32::from_be_bytes(raw)
+            });
+        }
+    }
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: channels,
+        pixels: Pixels::F32(data),
+    })
+}
+
+/// Reads a true-color or gray TGA, either uncompressed or run-length encoded.
+pub fn read_tga(path_to_file: &str) -> Result<Image, String> {
+    let bytes = read_all(path_to_file)?;
+    let invalid = |reason: &str| format!("couldn't decode {}: {}", path_to_file, reason);
+    if bytes.len() < 18 {
+        return Err(invalid("truncated header"));
+    }
+
+    let u16_at = |at: usize| bytes[at] as usize | (bytes[at + 1] as usize) << 8;
+    let image_type = bytes[2];
+    let width = u16_at(12);
+    let height = u16_at(14);
+    let depth = bytes[16] as usize;
+    let descriptor = bytes[17];
+    let rle = image_type == 10 || image_type == 11;
+    let channels = match (image_type, depth) {
+        (2, 24) | (10, 24) => 3,
+        (2, 32) | (10, 32) => 4,
+        (3, 8) | (11, 8) => 1,
+        (3, 16) | (11, 16) => 2,
+        _ => {
+            return Err(invalid(
+                "only 24 and 32-bit color or 8 and 16-bit gray are supported",
+            ))
+        }
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("bad size"));
+    }
+    // Skip the image ID and any color map.
+    let mut pos = 18 + bytes[0] as usize;
+    if bytes[1] != 0 {
+        pos += u16_at(5) * (bytes[7] as usize).div_ceil(8);
+    }
+
+    // Pixels come in file order, which is BGR(A) and may run bottom-up or right-to-left.
+    let n = width * height;
+    let mut raw = Vec::with_capacity(n * channels);
+    while raw.len() < n * channels {
+        let (count, repeat) = if rle {
+            let header = match bytes.get(pos) {
+                Some(&b) => b as usize,
+                None => return Err(invalid("truncated data")),
+            };
+            pos += 1;
+            ((header & 0x7f) + 1, header & 0x80 != 0)
+        } else {
+            (n, false)
+        };
+        let size = if repeat { channels } else { count * channels };
+        if pos + size > bytes.len() || raw.len() + count * channels > n * channels {
+            return Err(invalid("truncated data"));
+        }
+        if repeat {
+            for _ in 0..count {
+                raw.extend_from_slice(&bytes[pos..pos + channels]);
+            }
+        } else {
+            raw.extend_from_slice(&bytes[pos..pos + size]);
+        }
+        pos += size;
+    }
+
+    let top_down = descriptor & 0x20 != 0;
+    let right_to_left = descriptor & 0x10 != 0;
+    let mut data = vec![0; n * channels];
+    for y in 0..height {
+        for x in 0..width {
+            let src_y = if top_down { y } else { height - 1 - y };
+            let src_x = if right_to_left { width - 1 - x } else { x };
+            let src = (src_y * width + src_x) * channels;
+            let dst = (y * width + x) * channels;
+            data[dst..dst + channels].copy_from_slice(&raw[src..src + channels]);
+            if channels >= 3 {
+                data.swap(dst, dst + 2);
+            }
+        }
+    }
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: channels,
+        pixels: Pixels::U8(data),
+    })
+}
+
+/// Reads an uncompressed BMP with 1, 4, 8, 24 or 32 bits per pixel as RGB.
+pub fn read_bmp(path_to_file: &str) -> Result<Image, String> {
+    let bytes = read_all(path_to_file)?;
+    let invalid = |reason: &str| format!("couldn't decode {}: {}", path_to_file, reason);
+    if bytes.len() < 26 || &bytes[0..2] != b"BM" {
+        return Err(invalid("not a BMP file"));
+    }
+
+    let u16_at = |at: usize| bytes[at] as usize | (bytes[at + 1] as usize) << 8;
+    let u32_at = |at: usize| u16_at(at) | u16_at(at + 2) << 16;
+    let offset = u32_at(10);
+    let header_size = u32_at(14);
+    // The old OS/2 header has 16-bit sizes and 3-byte palette entries.
+    let (width, height, depth, compression, n_colors, entry_size) = if header_size == 12 {
+        (u16_at(18) as i64, u16_at(20) as i64, u16_at(24), 0, 0, 3)
+    } else if header_size >= 40 && bytes.len() >= 14 + header_size {
+        (
+            u32_at(18) as i32 as i64,
+            u32_at(22) as i32 as i64,
+            u16_at(28),
+            u32_at(30),
+            u32_at(46),
+            4,
+        )
+    } else {
+        return Err(invalid("unsupported header"));
+    };
+    if compression != 0 {
+        return Err(invalid("only uncompressed images are supported"));
+    }
+    if !matches!(depth, 1 | 4 | 8 | 24 | 32) {
+        return Err(invalid(&format!("unsupported {}-bit pixels", depth)));
+    }
+    // A negative height means the rows are stored from the top.
+    let top_down = height < 0;
+    let (width, height) = (
+        width.unsigned_abs() as usize,
+        height.unsigned_abs() as usize,
+    );
+    if width == 0 || height == 0 {
+        return Err(invalid("bad size"));
+    }
+
+    let mut palette = Vec::new();
+    if depth <= 8 {
+        let n = if n_colors == 0 { 1 << depth } else { n_colors };
+        let start = 14 + header_size;
+        if start + n * entry_size > bytes.len() {
+            return Err(invalid("truncated palette"));
+        }
+        for i in 0..n {
+            let at = start + i * entry_size;
+            palette.push([bytes[at + 2], bytes[at + 1], bytes[at]]);
+        }
+    }
+
+    // Rows are padded to a multiple of four bytes.
+    let stride = (width * depth).div_ceil(32) * 4;
+    if offset + stride * height > bytes.len() {
+        return Err(invalid("truncated data"));
+    }
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let line = &bytes[offset + row * stride..offset + (row + 1) * stride];
+        for x in 0..width {
+            let rgb = match depth {
+                24 | 32 => {
+                    let at = x * depth / 8;
+                    [line[at + 2], line[at + 1], line[at]]
+                }
+                _ => {
+                    let bit = x * depth;
+                    let index =
+                        (line[bit / 8] >> (8 - depth - bit % 8)) as usize & ((1 << depth) - 1);
+                    match palette.get(index) {
+                        Some(&color) => color,
+                        None => return Err(invalid("palette index out of range")),
+                    }
+                }
+            };
+            data.extend_from_slice(&rgb);
+        }
+    }
+    Ok(Image {
+        width: width,
+        height: height,
+        channels: 3,
+        pixels: Pixels::U8(data),
+    })
+}
+
+fn create(path_to_file: &str) -> Result<BufWriter<File>, String> {
+    match File::create(Path::new(path_to_file)) {
+        Err(why) => Err(format!("couldn't create {}: {}", path_to_file, why)),
+        Ok(file) => Ok(BufWriter::new(file)),
+    }
+}
+
+fn write_all(path_to_file: &str, bytes: &[u8]) -> Result<(), String> {
+    let mut w = create(path_to_file)?;
+    match w.write_all(bytes).and_then(|_| w.flush()) {
+        Err(why) => Err(format!("couldn't write {}: {}", path_to_file, why)),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Writes 16-bit RGB data, row by row from the top, as a PNG.
+pub fn write_to_png16(
+    path_to_file: &str,
+    data: &[u16],
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    let w = create(path_to_file)?;
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set(png::ColorType::RGB).set(png::BitDepth::Sixteen);
+    // PNG stores 16-bit samples big-endian.
+    let bytes: Vec<u8> = data.iter().flat_map(|x| x.to_be_bytes()).collect();
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&bytes));
+    match result {
+        Err(why) => Err(format!("couldn't write {}: {}", path_to_file, why)),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Writes linear RGB floats, row by row from the top, as a Radiance RGBE (.hdr) image.
+pub fn write_to_hdr(
+    path_to_file: &str,
+    data: &[f32],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let mut bytes = format!(
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )
+    .into_bytes();
+    // Scanlines are stored flat, which every reader accepts.
+    for px in data.chunks(3).take(width * height) {
+        let max = px[0].max(px[1]).max(px[2]);
+        if max.is_nan() || max < 1e-32 {
+            bytes.extend_from_slice(&[0, 0, 0, 0]);
+        } else {
+            let exponent = max.log2().floor() as i32 + 1;
+            let scale = 256.0 / 2.0_f32.powi(exponent);
+            let mantissa = |x: f32| (x.max(0.0) * scale).min(255.0) as u8;
+            bytes.extend_from_slice(&[
+                mantissa(px[0]),
+                mantissa(px[1]),
+                mantissa(px[2]),
+                (exponent + 128).clamp(0, 255) as u8,
+            ]);
+        }
+    }
+    write_all(path_to_file, &bytes)
+}
+
+/// Writes linear RGB floats, row by row from the top, as a little-endian PFM image.
+pub fn write_to_pfm(
+    path_to_file: &str,
+    data: &[f32],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    // PFM rows go from the bottom up.
+    for row in data.chunks(width * 3).take(height).rev() {
+        for x in row {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+    write_all(path_to_file, &bytes)
+}
+
+fn exr_attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(kind.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(value);
+}
+
+/// Writes linear RGB floats, row by row from the top, as an uncompressed
+/// scanline OpenEXR image with 32-bit float channels.
+pub fn write_to_exr(
+    path_to_file: &str,
+    data: &[f32],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    bytes.extend_from_slice(&2_u32.to_le_bytes());
+
+    // Channels are listed, and stored, in alphabetical order.
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&2_i32.to_le_bytes()); // FLOAT
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    channels.push(0);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut bytes, "channels", "chlist", &channels);
+    exr_attribute(&mut bytes, "compression", "compression", &[0]);
+    exr_attribute(&mut bytes, "dataWindow", "box2i", &window);
+    exr_attribute(&mut bytes, "displayWindow", "box2i", &window);
+    exr_attribute(&mut bytes, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(
+        &mut bytes,
+        "pixelAspectRatio",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    let mut center = Vec::new();
+    center.extend_from_slice(&0.0_f32.to_le_bytes());
+    center.extend_from_slice(&0.0_f32.to_le_bytes());
+    exr_attribute(&mut bytes, "screenWindowCenter", "v2f", &center);
+    exr_attribute(
+        &mut bytes,
+        "screenWindowWidth",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    bytes.push(0);
+
+    // Without compression every block is one scanline of the same size.
+    let line_size = width * 3 * 4;
+    let block_size = 8 + line_size;
+    let table_end = bytes.len() + height * 8;
+    for y in 0..height {
+        bytes.extend_from_slice(&((table_end + y * block_size) as u64).to_le_bytes());
+    }
+    for (y, row) in data.chunks(width * 3).take(height).enumerate() {
+        bytes.extend_from_slice(&(y as i32).to_le_bytes());
+        bytes.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for c in [2, 1, 0] {
+            for px in row.chunks(3) {
+                bytes.extend_from_slice(&px[c].to_le_bytes());
+            }
+        }
+    }
+    write_all(path_to_file, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 3;
+    const HEIGHT: usize = 2;
+
+    /// A path in the temporary directory no other test run writes to.
+    fn temp_file(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        let file = format!("raytrace-{}-{}", std::process::id(), name);
+        dir.join(file).to_string_lossy().into_owned()
+    }
+
+    /// Reads back what `write` put in the file `name`, then removes it.
+    fn round_trip(name: &str, write: impl Fn(&str) -> Result<(), String>) -> Image {
+        let path = temp_file(name);
+        write(&path).unwrap();
+        let image = read_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        let image = image.unwrap();
+        assert_eq!((image.width, image.height), (WIDTH, HEIGHT));
+        image
+    }
+
+    /// RGB bytes, row by row from the top, all different.
+    fn rgb8() -> Vec<u8> {
+        (0..WIDTH * HEIGHT * 3)
+            .map(|i| (i * 14 + 3) as u8)
+            .collect()
+    }
+
+    fn bytes(image: &Image) -> &[u8] {
+        match &image.pixels {
+            Pixels::U8(data) => data,
+            Pixels::F32(_) => panic!("expected 8-bit samples"),
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let data = rgb8();
+        let image = round_trip("8.png", |path| {
+            write_to_png(path, &data, WIDTH as u32, HEIGHT as u32)
+        });
+        assert_eq!(image.channels, 3);
+        assert_eq!(bytes(&image), &data[..]);
+    }
+
+    #[test]
+    fn png16_round_trip() {
+        let data: Vec<u16> = (0..WIDTH * HEIGHT * 3).map(|i| (i * 3851) as u16).collect();
+        let image = round_trip("16.png", |path| {
+            write_to_png16(path, &data, WIDTH as u32, HEIGHT as u32)
+        });
+        let expected: Vec<f32> = data.iter().map(|&x| x as f32 / 65535.0).collect();
+        assert_eq!(image.to_rgb(), expected);
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let data = rgb8();
+        let image = round_trip("binary.ppm", |path| {
+            write_to_ppm(path, &data, WIDTH, HEIGHT)
+        });
+        assert_eq!(bytes(&image), &data[..]);
+        let image = round_trip("ascii.ppm", |path| {
+            write_to_ppm_ascii(path, &data, WIDTH, HEIGHT)
+        });
+        assert_eq!(bytes(&image), &data[..]);
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let data: Vec<f32> = (0..WIDTH * HEIGHT * 3).map(|i| i as f32 * 0.37).collect();
+        let image = round_trip("float.pfm", |path| write_to_pfm(path, &data, WIDTH, HEIGHT));
+        assert_eq!(image.to_rgb(), data);
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        let data: Vec<f32> = (0..WIDTH * HEIGHT * 3)
+            .map(|i| (1.0 + (i % 3) as f32) * 0.4_f32.powi(i as i32 / 3 - 2))
+            .collect();
+        let image = round_trip("radiance.hdr", |path| {
+            write_to_hdr(path, &data, WIDTH, HEIGHT)
+        });
+        // RGBE keeps 8 bits of mantissa for the brightest channel of each pixel.
+        for (read, written) in image.to_rgb().chunks(3).zip(data.chunks(3)) {
+            let max = written[0].max(written[1]).max(written[2]);
+            for c in 0..3 {
+                assert!((read[c] - written[c]).abs() <= max / 128.0);
+            }
+        }
+    }
+
+    #[test]
+    fn exr_layout() {
+        let data: Vec<f32> = (0..WIDTH * HEIGHT * 3).map(|i| i as f32 * 0.25).collect();
+        let path = temp_file("scanlines.exr");
+        write_to_exr(&path, &data, WIDTH, HEIGHT).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let i32_at = |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let f32_at = |at: usize| f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let string_at = |at: usize| {
+            let end = at + bytes[at..].iter().position(|&b| b == 0).unwrap();
+            (String::from_utf8(bytes[at..end].to_vec()).unwrap(), end + 1)
+        };
+        assert_eq!(bytes[0..4], [0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(i32_at(4), 2);
+
+        // The header is name, type, size and value until an empty name.
+        let mut attributes = std::collections::HashMap::new();
+        let mut pos = 8;
+        loop {
+            let (name, next) = string_at(pos);
+            if name.is_empty() {
+                pos = next;
+                break;
+            }
+            let (kind, next) = string_at(next);
+            let size = i32_at(next) as usize;
+            attributes.insert(name, (kind, next + 4, size));
+            pos = next + 4 + size;
+        }
+        let attribute = |name: &str, kind: &str| {
+            let (found, at, size) = &attributes[name];
+            assert_eq!(found, kind, "type of {}", name);
+            (*at, *size)
+        };
+
+        let (at, size) = attribute("channels", "chlist");
+        let mut names = Vec::new();
+        let mut c = at;
+        while bytes[c] != 0 {
+            let (name, next) = string_at(c);
+            assert_eq!(i32_at(next), 2, "channel {} is not FLOAT", name);
+            assert_eq!((i32_at(next + 8), i32_at(next + 12)), (1, 1));
+            names.push(name);
+            c = next + 16;
+        }
+        assert_eq!(names, ["B", "G", "R"]);
+        assert_eq!(c + 1, at + size);
+        let (at, _) = attribute("compression", "compression");
+        assert_eq!(bytes[at], 0);
+        let window = [0, 0, WIDTH as i32 - 1, HEIGHT as i32 - 1];
+        for name in ["dataWindow", "displayWindow"] {
+            let (at, _) = attribute(name, "box2i");
+            let found: Vec<i32> = (0..4).map(|k| i32_at(at + 4 * k)).collect();
+            assert_eq!(found, window, "{}", name);
+        }
+        let (at, _) = attribute("lineOrder", "lineOrder");
+        assert_eq!(bytes[at], 0);
+        let (at, _) = attribute("pixelAspectRatio", "float");
+        assert_eq!(f32_at(at), 1.0);
+        let (at, _) = attribute("screenWindowCenter", "v2f");
+        assert_eq!((f32_at(at), f32_at(at + 4)), (0.0, 0.0));
+        let (at, _) = attribute("screenWindowWidth", "float");
+        assert_eq!(f32_at(at), 1.0);
+
+        // One offset per scanline, each pointing at its y, its size and its samples.
+        let line_size = WIDTH * 3 * 4;
+        let table = pos;
+        for y in 0..HEIGHT {
+            let offset = u64_at(table + 8 * y) as usize;
+            assert_eq!(offset, table + 8 * HEIGHT + y * (8 + line_size));
+            assert_eq!(i32_at(offset), y as i32);
+            assert_eq!(i32_at(offset + 4), line_size as i32);
+        }
+        assert_eq!(bytes.len(), table + HEIGHT * (16 + line_size));
+
+        // The first scanline holds all its blue samples, then green, then red.
+        let first = u64_at(table) as usize + 8;
+        for (k, c) in [2, 1, 0].into_iter().enumerate() {
+            for x in 0..WIDTH {
+                assert_eq!(f32_at(first + (k * WIDTH + x) * 4), data[x * 3 + c]);
+            }
+        }
+    }
+
+    /// A TGA file of 24-bit pixels in file order, BGR and bottom-up unless
+    /// `descriptor` says otherwise.
+    fn tga(image_type: u8, descriptor: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&(WIDTH as u16).to_le_bytes());
+        bytes.extend_from_slice(&(HEIGHT as u16).to_le_bytes());
+        bytes.extend_from_slice(&[24, descriptor]);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    /// `rgb8` as BGR pixels with the rows in `rows` order.
+    fn bgr_rows(rows: impl Iterator<Item = usize>, padding: usize) -> Vec<u8> {
+        let data = rgb8();
+        let mut body = Vec::new();
+        for y in rows {
+            for px in data[y * WIDTH * 3..(y + 1) * WIDTH * 3].chunks(3) {
+                body.extend_from_slice(&[px[2], px[1], px[0]]);
+            }
+            body.extend(std::iter::repeat_n(0, padding));
+        }
+        body
+    }
+
+    #[test]
+    fn tga_round_trip() {
+        let bottom_up = tga(2, 0, &bgr_rows((0..HEIGHT).rev(), 0));
+        let image = round_trip("bottom_up.tga", |path| write_all(path, &bottom_up));
+        assert_eq!(bytes(&image), &rgb8()[..]);
+
+        // Run-length packets: one raw pixel, then the rest as one repeated pixel.
+        let body = vec![0x00, 1, 2, 3, 0x80 | (WIDTH * HEIGHT - 2) as u8, 4, 5, 6];
+        let top_down = tga(10, 0x20, &body);
+        let image = round_trip("rle.tga", |path| write_all(path, &top_down));
+        let mut expected = vec![3, 2, 1];
+        for _ in 1..WIDTH * HEIGHT {
+            expected.extend_from_slice(&[6, 5, 4]);
+        }
+        assert_eq!(bytes(&image), &expected[..]);
+    }
+
+    #[test]
+    fn bmp_round_trip() {
+        // Rows of three 24-bit pixels are padded from 9 to 12 bytes.
+        let pixels = bgr_rows((0..HEIGHT).rev(), 3);
+        let offset = 14 + 40;
+        let mut bytes_out = b"BM".to_vec();
+        bytes_out.extend_from_slice(&((offset + pixels.len()) as u32).to_le_bytes());
+        bytes_out.extend_from_slice(&[0, 0, 0, 0]);
+        bytes_out.extend_from_slice(&(offset as u32).to_le_bytes());
+        bytes_out.extend_from_slice(&40_u32.to_le_bytes());
+        bytes_out.extend_from_slice(&(WIDTH as i32).to_le_bytes());
+        bytes_out.extend_from_slice(&(HEIGHT as i32).to_le_bytes());
+        bytes_out.extend_from_slice(&1_u16.to_le_bytes());
+        bytes_out.extend_from_slice(&24_u16.to_le_bytes());
+        bytes_out.extend_from_slice(&[0; 24]);
+        bytes_out.extend_from_slice(&pixels);
+        let image = round_trip("rows.bmp", |path| write_all(path, &bytes_out));
+        assert_eq!(image.channels, 3);
+        assert_eq!(bytes(&image), &rgb8()[..]);
+    }
 }
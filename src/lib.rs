@@ -0,0 +1,71 @@
+//! A small path tracer based on the "Ray Tracing in One Weekend" series.
+//!
+//! A [`Scene`] is either loaded from a JSON file with [`Scene::new`] or put
+//! together in code from [`Hittable`] shapes, [`Material`]s and [`Texture`]s,
+//! and then turned into an in-memory [`Framebuffer`] with [`render()`].
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use raytrace::backgrounds::GradientBackground;
+//! use raytrace::geometry::sphere::Sphere;
+//! use raytrace::materials::Lambertian;
+//! use raytrace::textures::ConstantTexture;
+//! use raytrace::{render, Camera, Scene, Vec3};
+//!
+//! let camera = Camera::new(
+//!     Vec3::new(0.0, 0.0, 5.0),
+//!     Vec3::new(0.0, 0.0, 0.0),
+//!     Vec3::new(0.0, 1.0, 0.0),
+//!     40.0,
+//!     1.0,
+//!     0.0,
+//!     5.0,
+//!     0.0,
+//!     1.0,
+//! );
+//! let mut scene = Scene::with_camera(camera);
+//! scene.render.background = Arc::new(GradientBackground::default());
+//! let gray = Arc::new(Lambertian::new(Arc::new(ConstantTexture::new(Vec3::new(
+//!     0.5, 0.5, 0.5,
+//! )))));
+//! scene
+//!     .shapes
+//!     .push(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, gray)));
+//!
+//! let frame = render(&scene, 4, 0);
+//! assert_eq!(frame.data.len(), frame.width * frame.height * 3);
+//! ```
+
+#![allow(
+    clippy::redundant_field_names,
+    clippy::too_many_arguments,
+    clippy::upper_case_acronyms
+)]
+
+extern crate json;
+extern crate num_cpus;
+
+pub mod backgrounds;
+pub mod camera;
+pub mod film;
+pub mod geometry;
+pub mod image;
+pub mod linalg;
+pub mod materials;
+pub mod random;
+pub mod render;
+pub mod scene;
+pub mod stats;
+pub mod textures;
+pub mod tiles;
+pub mod tonemap;
+
+pub use backgrounds::Background;
+pub use camera::Camera;
+pub use geometry::hittable::{HitRecord, Hittable};
+pub use linalg::{Ray, Vec3};
+pub use materials::Material;
+pub use render::{render, Framebuffer};
+pub use scene::{RenderSettings, Scene};
+pub use textures::Texture;
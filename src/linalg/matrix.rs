@@ -0,0 +1,245 @@
+use super::vector::Vec3;
+
+/// A 4x4 matrix acting on column vectors, stored row by row.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4(pub [[f32; 4]; 4]);
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: &Vec3) -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, offset.0],
+            [0.0, 1.0, 0.0, offset.1],
+            [0.0, 0.0, 1.0, offset.2],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(factors: &Vec3) -> Mat4 {
+        Mat4([
+            [factors.0, 0.0, 0.0, 0.0],
+            [0.0, factors.1, 0.0, 0.0],
+            [0.0, 0.0, factors.2, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Counter-clockwise rotation by `angle` degrees about `axis`, looking against the axis.
+    pub fn rotation(axis: &Vec3, angle: f32) -> Mat4 {
+        let a = Vec3::unit(axis);
+        let radians = (std::f32::consts::PI / 180.0) * angle;
+        let (s, c) = radians.sin_cos();
+        let t = 1.0 - c;
+        Mat4([
+            [
+                t * a.0 * a.0 + c,
+                t * a.0 * a.1 - s * a.2,
+                t * a.0 * a.2 + s * a.1,
+                0.0,
+            ],
+            [
+                t * a.0 * a.1 + s * a.2,
+                t * a.1 * a.1 + c,
+                t * a.1 * a.2 - s * a.0,
+                0.0,
+            ],
+            [
+                t * a.0 * a.2 - s * a.1,
+                t * a.1 * a.2 + s * a.0,
+                t * a.2 * a.2 + c,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Rotation by Euler angles in degrees, applied about X first, then Y, then Z.
+    pub fn euler_rotation(angles: &Vec3) -> Mat4 {
+        Mat4::rotation(&Vec3::new(0.0, 0.0, 1.0), angles.2)
+            * Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), angles.1)
+            * Mat4::rotation(&Vec3::new(1.0, 0.0, 0.0), angles.0)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let m = &self.0;
+        let mut out = [[0.0; 4]; 4];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = m[j][i];
+            }
+        }
+        Mat4(out)
+    }
+
+    /// The determinant of the upper-left 3x3 part: how much the matrix scales volumes.
+    pub fn linear_determinant(&self) -> f32 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// The inverse by cofactor expansion, `None` for a singular matrix: one whose
+    /// determinant is tiny next to the product of the lengths of its columns, which
+    /// bounds it, so that the scale of the matrix doesn't matter.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let m = &self.0;
+        let s0 = m[0][0] * m[1][1] - m[1][0] * m[0][1];
+        let s1 = m[0][0] * m[1][2] - m[1][0] * m[0][2];
+        let s2 = m[0][0] * m[1][3] - m[1][0] * m[0][3];
+        let s3 = m[0][1] * m[1][2] - m[1][1] * m[0][2];
+        let s4 = m[0][1] * m[1][3] - m[1][1] * m[0][3];
+        let s5 = m[0][2] * m[1][3] - m[1][2] * m[0][3];
+
+        let c5 = m[2][2] * m[3][3] - m[3][2] * m[2][3];
+        let c4 = m[2][1] * m[3][3] - m[3][1] * m[2][3];
+        let c3 = m[2][1] * m[3][2] - m[3][1] * m[2][2];
+        let c2 = m[2][0] * m[3][3] - m[3][0] * m[2][3];
+        let c1 = m[2][0] * m[3][2] - m[3][0] * m[2][2];
+        let c0 = m[2][0] * m[3][1] - m[3][0] * m[2][1];
+
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        let columns: f32 = (0..4)
+            .map(|j| m.iter().map(|row| row[j] * row[j]).sum::<f32>().sqrt())
+            .product();
+        let inv = 1.0 / det;
+        if det.abs() <= 1e-6 * columns || !inv.is_finite() {
+            return None;
+        }
+
+        Some(Mat4([
+            [
+                (m[1][1] * c5 - m[1][2] * c4 + m[1][3] * c3) * inv,
+                (-m[0][1] * c5 + m[0][2] * c4 - m[0][3] * c3) * inv,
+                (m[3][1] * s5 - m[3][2] * s4 + m[3][3] * s3) * inv,
+                (-m[2][1] * s5 + m[2][2] * s4 - m[2][3] * s3) * inv,
+            ],
+            [
+                (-m[1][0] * c5 + m[1][2] * c2 - m[1][3] * c1) * inv,
+                (m[0][0] * c5 - m[0][2] * c2 + m[0][3] * c1) * inv,
+                (-m[3][0] * s5 + m[3][2] * s2 - m[3][3] * s1) * inv,
+                (m[2][0] * s5 - m[2][2] * s2 + m[2][3] * s1) * inv,
+            ],
+            [
+                (m[1][0] * c4 - m[1][1] * c2 + m[1][3] * c0) * inv,
+                (-m[0][0] * c4 + m[0][1] * c2 - m[0][3] * c0) * inv,
+                (m[3][0] * s4 - m[3][1] * s2 + m[3][3] * s0) * inv,
+                (-m[2][0] * s4 + m[2][1] * s2 - m[2][3] * s0) * inv,
+            ],
+            [
+                (-m[1][0] * c3 + m[1][1] * c1 - m[1][2] * c0) * inv,
+                (m[0][0] * c3 - m[0][1] * c1 + m[0][2] * c0) * inv,
+                (-m[3][0] * s3 + m[3][1] * s1 - m[3][2] * s0) * inv,
+                (m[2][0] * s3 - m[2][1] * s1 + m[2][2] * s0) * inv,
+            ],
+        ]))
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.0;
+        let x = m[0][0] * p.0 + m[0][1] * p.1 + m[0][2] * p.2 + m[0][3];
+        let y = m[1][0] * p.0 + m[1][1] * p.1 + m[1][2] * p.2 + m[1][3];
+        let z = m[2][0] * p.0 + m[2][1] * p.1 + m[2][2] * p.2 + m[2][3];
+        let w = m[3][0] * p.0 + m[3][1] * p.1 + m[3][2] * p.2 + m[3][3];
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x / w, y / w, z / w)
+        }
+    }
+
+    /// Transforms a direction, ignoring the translation part.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+            m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+            m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+        )
+    }
+}
+
+impl std::ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut out = [[0.0; 4]; 4];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        Mat4(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &Mat4, b: &Mat4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!(
+                    (a.0[i][j] - b.0[i][j]).abs() < 1e-5,
+                    "{:?} is not close to {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let m = Mat4::translation(&Vec3::new(1.0, -2.0, 3.5))
+            * Mat4::rotation(&Vec3::new(1.0, 2.0, -0.5), 37.0)
+            * Mat4::scaling(&Vec3::new(2.0, 0.5, 3.0));
+        let inverse = m.inverse().unwrap();
+        assert_near(&(m * inverse), &Mat4::identity());
+        assert_near(&(inverse * m), &Mat4::identity());
+    }
+
+    #[test]
+    fn inverse_of_a_general_matrix() {
+        let m = Mat4([
+            [2.0, 1.0, 0.0, 3.0],
+            [0.0, 1.0, 4.0, -1.0],
+            [1.0, 0.0, 1.0, 2.0],
+            [0.5, -1.0, 0.0, 1.0],
+        ]);
+        assert_near(&(m * m.inverse().unwrap()), &Mat4::identity());
+    }
+
+    #[test]
+    fn small_scale_has_an_inverse() {
+        let m = Mat4::translation(&Vec3::new(3.0, 0.0, -2.0))
+            * Mat4::scaling(&Vec3::new(0.00001, 0.00001, 0.00001));
+        let inverse = m.inverse().unwrap();
+        assert_near(&(m * inverse), &Mat4::identity());
+        let p = Vec3::new(4.0, -5.0, 6.0);
+        let q = inverse.transform_point(&m.transform_point(&p));
+        assert!((q - p).length() < 1e-3);
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert_eq!(Mat4::scaling(&Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+        let mut m = Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), 30.0);
+        m.0[2] = m.0[0];
+        assert_eq!(m.inverse(), None);
+        let tiny = Mat4::scaling(&Vec3::new(0.00001, 0.00001, 0.00001));
+        assert_eq!(
+            (tiny * Mat4::scaling(&Vec3::new(1.0, 0.0, 1.0))).inverse(),
+            None
+        );
+    }
+}
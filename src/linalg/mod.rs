@@ -1,5 +1,9 @@
+pub use self::matrix::Mat4;
+pub use self::onb::ONB;
 pub use self::ray::Ray;
 pub use self::vector::Vec3;
 
+pub mod matrix;
+pub mod onb;
 pub mod ray;
 pub mod vector;
@@ -0,0 +1,27 @@
+use super::vector::Vec3;
+
+/// An orthonormal basis with `w` along a given direction.
+pub struct ONB {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl ONB {
+    pub fn from_w(n: &Vec3) -> Self {
+        let w = Vec3::unit(n);
+        let a = if w.0.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit(&Vec3::cross(&w, &a));
+        let u = Vec3::cross(&w, &v);
+        Self { u: u, v: v, w: w }
+    }
+
+    /// Turns coordinates in this basis into a world-space vector.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.0 * self.u + a.1 * self.v + a.2 * self.w
+    }
+}
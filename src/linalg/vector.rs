@@ -14,6 +14,11 @@ impl Vec3 {
         self.0 * self.0 + self.1 * self.1 + self.2 * self.2
     }
 
+    /// Luminance of a linear RGB color (Rec. 709 weights).
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
     pub fn unit(vector: &Vec3) -> Vec3 {
         vector / vector.length()
     }
@@ -1,169 +1,378 @@
-mod camera;
-mod geometry;
-mod image;
-mod linalg;
-mod materials;
-mod random;
-mod scene;
-mod textures;
-
-use geometry::bvh_node::BVHNode;
-use geometry::hittable::Hittable;
-use geometry::hittable_list::HittableList;
-use linalg::{Ray, Vec3};
+#![allow(clippy::redundant_field_names)]
+
+use raytrace::film::Film;
+use raytrace::image::{self, Image, Pixels};
+use raytrace::random::pcg::hash;
+use raytrace::render::{self, Framebuffer};
+use raytrace::scene::{ImageFormat, RenderSettings, Scene};
+use raytrace::tiles::Tile;
+use raytrace::tonemap::{ToneMap, ToneMapOperator, TransferFunction};
+
+mod options;
+mod report;
+
+use options::{Options, OptionsError};
+use report::{Checkpoint, Monitor, Report};
 
 use std::env;
-use std::sync::{mpsc, Arc};
-use std::time::SystemTime;
-
-use rand::Rng;
-use threadpool::ThreadPool;
-
-extern crate json;
-extern crate num_cpus;
-
-fn threaded_color(r: &Ray, world: &Arc<dyn Hittable>, depth: i32) -> Vec3 {
-    match world.hit(r, 0.001, std::f32::MAX) {
-        Some(rec) => {
-            let emitted = rec.mat.emit(rec.u, rec.v, &rec.p);
-            if depth < 50 {
-                match rec.mat.scatter(r, &rec) {
-                    Some((attenuation, scattered)) => {
-                        emitted + attenuation * threaded_color(&scattered, world, depth + 1)
-                    }
-                    None => emitted,
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn exit_with_usage_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    eprintln!("Run with --help for usage.");
+    process::exit(2);
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+/// What a cropped render keeps of the image already at the output path: everything
+/// outside `crop`.
+struct Backdrop {
+    image: Image,
+    crop: Tile,
+}
+
+impl Backdrop {
+    /// Copies the backdrop over the pixels of `data`, RGB rows from the top, that lie
+    /// outside the crop, converting its samples with `convert`.
+    fn paste<T: Copy>(&self, data: &mut [T], convert: impl Fn(f32) -> T) {
+        let (width, height) = (self.image.width, self.image.height);
+        let crop = &self.crop;
+        for row in 0..height {
+            let y = height - 1 - row;
+            for x in 0..width {
+                let inside = (crop.x..crop.x + crop.width).contains(&x)
+                    && (crop.y..crop.y + crop.height).contains(&y);
+                if !inside {
+                    let color = self.image.rgb(x, row);
+                    let i = (row * width + x) * 3;
+                    data[i..i + 3].copy_from_slice(&[
+                        convert(color.0),
+                        convert(color.1),
+                        convert(color.2),
+                    ]);
                 }
-            } else {
-                emitted
             }
         }
-        None => Vec3::new(0.0, 0.0, 0.0),
     }
 }
 
-fn partial_render(
-    camera: Arc<camera::Camera>,
-    world: Arc<dyn Hittable>,
-    start_x: usize,
-    start_y: usize,
-    width: usize,
-    height: usize,
-    n_samples: usize,
-    picture_width: usize,
-    picture_height: usize,
-) -> Vec<u8> {
-    let mut data = Vec::with_capacity(width * height * 3);
-
-    let mut rng = rand::thread_rng();
-    for y in (start_y..(start_y + height)).rev() {
-        for x in start_x..(start_x + width) {
-            let mut col = Vec3::new(0.0, 0.0, 0.0);
-            for _s in 0..n_samples {
-                let r1: f32 = rng.gen();
-                let r2: f32 = rng.gen();
-                let u = (x as f32 + r1) / (picture_width as f32);
-                let v = (y as f32 + r2) / (picture_height as f32);
-                let r = camera.get_ray(u, v);
-                col += threaded_color(&r, &world, 0);
+/// Writes `frame` to the output file, over `backdrop` if given. The image goes to a
+/// temporary file first, so an interrupted write never leaves a broken output behind.
+fn write_image(
+    output_file: &str,
+    format: ImageFormat,
+    tone_map: &ToneMap,
+    frame: &Framebuffer,
+    backdrop: Option<&Backdrop>,
+) -> Result<(), String> {
+    let path = &format!("{}.tmp", output_file);
+    let (w, h) = (frame.width, frame.height);
+    let rgb8 = || {
+        let mut data = frame.to_rgb8(tone_map);
+        if let Some(backdrop) = backdrop {
+            backdrop.paste(&mut data, |x| (x * 255.0).round() as u8);
+        }
+        data
+    };
+    let floats = || {
+        let mut data = frame.data.clone();
+        if let Some(backdrop) = backdrop {
+            backdrop.paste(&mut data, |x| x);
+        }
+        data
+    };
+    match format {
+        ImageFormat::Png => image::write_to_png(path, &rgb8(), w as u32, h as u32),
+        ImageFormat::Ppm => image::write_to_ppm(path, &rgb8(), w, h),
+        ImageFormat::PpmAscii => image::write_to_ppm_ascii(path, &rgb8(), w, h),
+        ImageFormat::Png16 => {
+            let mut data = frame.to_rgb16(tone_map);
+            if let Some(backdrop) = backdrop {
+                backdrop.paste(&mut data, |x| (x * 65535.0).round() as u16);
             }
-            col = col / (n_samples as f32);
+            image::write_to_png16(path, &data, w as u32, h as u32)
+        }
+        ImageFormat::Hdr => image::write_to_hdr(path, &floats(), w, h),
+        ImageFormat::Pfm => image::write_to_pfm(path, &floats(), w, h),
+        ImageFormat::Exr => image::write_to_exr(path, &floats(), w, h),
+    }?;
+    match fs::rename(path, output_file) {
+        Err(why) => Err(format!("couldn't write {}: {}", output_file, why)),
+        Ok(()) => Ok(()),
+    }
+}
 
-            if col.0 > 1.0 {
-                data.push(255);
-            } else {
-                data.push((255.99 * col.0.sqrt()) as u8);
-            }
+/// The image at the output path, if there is one, for a render cropped to `crop`.
+fn read_backdrop(settings: &RenderSettings, crop: Tile) -> Result<Backdrop, String> {
+    let path = &settings.output_file;
+    // The render reaches a little beyond the crop, which must not show either.
+    if !Path::new(path).exists() {
+        let (width, height) = (settings.width, settings.height);
+        return Ok(Backdrop {
+            image: Image {
+                width: width,
+                height: height,
+                channels: 3,
+                pixels: Pixels::U8(vec![0; width * height * 3]),
+            },
+            crop: crop,
+        });
+    }
+    if settings.format == ImageFormat::Exr {
+        return Err(format!(
+            "can't crop into {}: EXR images can't be read",
+            path
+        ));
+    }
+    let image = image::read_image(path)?;
+    if (image.width, image.height) != (settings.width, settings.height) {
+        return Err(format!(
+            "can't crop into {}: it is {}x{}, not {}x{}",
+            path, image.width, image.height, settings.width, settings.height
+        ));
+    }
+    Ok(Backdrop {
+        image: image,
+        crop: crop,
+    })
+}
 
-            if col.1 > 1.0 {
-                data.push(255);
-            } else {
-                data.push((255.99 * col.1.sqrt()) as u8);
-            }
+/// Tells the render a checkpoint belongs to from any other: the scene file, and every
+/// setting that changes which samples are taken or how they add up.
+fn checkpoint_key(scene_file: &str, settings: &RenderSettings, seed: u64) -> Result<u64, String> {
+    let mut bytes =
+        fs::read(scene_file).map_err(|why| format!("couldn't read {}: {}", scene_file, why))?;
+    let described = format!(
+        "{}x{} {} {} {} {} {} {:?} {:?} {:?} {:?}",
+        settings.width,
+        settings.height,
+        settings.n_samples,
+        settings.max_depth,
+        settings.rr_depth,
+        settings.light_sampling,
+        seed,
+        settings.sampler,
+        settings.filter,
+        settings.adaptive,
+        settings.crop
+    );
+    bytes.extend_from_slice(described.as_bytes());
+    let words: Vec<u64> = bytes
+        .chunks(8)
+        .map(|chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        })
+        .collect();
+    Ok(hash(&words))
+}
 
-            if col.2 > 1.0 {
-                data.push(255);
-            } else {
-                data.push((255.99 * col.2.sqrt()) as u8);
-            }
+/// Writes how many samples each pixel got, scaled so that `max_samples` is white in
+/// 8 and 16-bit formats and as plain counts in float formats.
+fn write_sample_map(path: &str, frame: &Framebuffer, max_samples: usize) -> Result<(), String> {
+    let mut map = Framebuffer::new(frame.width, frame.height);
+    for (pixel, &n) in frame.samples.iter().enumerate() {
+        for c in 0..3 {
+            map.data[pixel * 3 + c] = n as f32;
         }
     }
-    data
+    let tone_map = ToneMap {
+        exposure: -(max_samples as f32).log2(),
+        operator: ToneMapOperator::Clamp,
+        transfer: TransferFunction::Gamma(1.0),
+    };
+    let format = ImageFormat::from_extension(path).unwrap_or(ImageFormat::Png);
+    write_image(path, format, &tone_map, &map, None)
 }
 
-const BLOCK: usize = 16;
+/// Makes Ctrl-C set `stop`, so the render ends early but still writes its image.
+/// A second Ctrl-C ends the process straight away.
+fn catch_interrupt(stop: Arc<AtomicBool>) {
+    let mut interrupted = false;
+    let result = ctrlc::set_handler(move || {
+        if interrupted {
+            process::exit(130);
+        }
+        interrupted = true;
+        stop.store(true, Ordering::Relaxed);
+    });
+    if let Err(why) = result {
+        eprintln!(
+            "warning: Ctrl-C will end the render without saving it: {}",
+            why
+        );
+    }
+}
 
 fn main() {
-    let start = SystemTime::now();
-
-    let width = 512;
-    let height = 512;
-    let n_samples = 250;
-    let mut data: Vec<u8> = Vec::with_capacity(width * height * 3);
-    for _ in 0..(width * height * 3) {
-        data.push(0);
-    }
-
-    let args: std::vec::Vec<String> = env::args().collect();
-    let scene_file = &args[1];
-    let output_file = &args[2];
-
-    let active_scene = scene::Scene::new(scene_file);
-
-    let camera = Arc::new(active_scene.camera);
-    let mut _world = HittableList::new();
-    for shape in active_scene.shapes {
-        _world.push(shape.clone());
-    }
-    let world = Arc::new(BVHNode::new(_world.list.as_mut_slice(), 0.0, 1.0));
-
-    // data = partial_render(camera, world, 0, 0, width, height, n_samples, width, height);
-    let pool = ThreadPool::new(num_cpus::get());
-    let (sender, receiver) = mpsc::channel();
-    for j in (0..height).step_by(BLOCK) {
-        for i in (0..width).step_by(BLOCK) {
-            let sender = sender.clone();
-            let camera_copy = camera.clone();
-            let world_copy = world.clone();
-            pool.execute(move || {
-                let rendered = partial_render(
-                    camera_copy,
-                    world_copy,
-                    i,
-                    j,
-                    BLOCK,
-                    BLOCK,
-                    n_samples,
-                    width,
-                    height,
-                );
-                sender.send((i, j, rendered)).unwrap();
-            });
+    let start = Instant::now();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(OptionsError::Help) => {
+            println!("{}", options::USAGE);
+            return;
         }
+        Err(e) => exit_with_usage_error(&e.to_string()),
+    };
+    let mut active_scene =
+        match Scene::new_with_overrides(&options.scene_file, |s| options.override_settings(s)) {
+            Ok(scene) => scene,
+            Err(e) => exit_with_error(&e.to_string()),
+        };
+    for warning in &active_scene.warnings {
+        eprintln!("warning: {}: {}", options.scene_file, warning);
+    }
+    if let Err(e) = active_scene.fit_camera() {
+        exit_with_error(&format!("{}: {}", options.scene_file, e));
+    }
+    let loaded = start.elapsed();
+
+    let settings = &active_scene.render;
+    if settings
+        .crop
+        .is_some_and(|crop| crop.width == 0 || crop.height == 0)
+    {
+        exit_with_error(&format!(
+            "--crop leaves no pixels of the {}x{} image",
+            settings.width, settings.height
+        ));
+    }
+    // The scene may turn adaptive sampling on instead of --adaptive.
+    if options.min_samples.is_some() && settings.adaptive.is_none() {
+        exit_with_usage_error("--min-spp needs --adaptive or adaptive sampling in the scene");
+    }
+    let mut bvh_time = Duration::ZERO;
+    for (label, stats) in active_scene.bvh_stats.iter() {
+        println!("BVH for {}: {}", label, stats);
+        bvh_time += stats.build_time;
     }
-    for _j in (0..height).step_by(BLOCK) {
-        for _i in (0..width).step_by(BLOCK) {
-            let (start_x, start_y, partial_data) = receiver.recv().unwrap();
-            let point = (height - start_y - BLOCK) * width * 3 + start_x * 3;
-            for y in (0..BLOCK).rev() {
-                for x in 0..BLOCK {
-                    data[point + y * width * 3 + x * 3] = partial_data[y * BLOCK * 3 + x * 3];
-                    data[point + y * width * 3 + x * 3 + 1] =
-                        partial_data[y * BLOCK * 3 + x * 3 + 1];
-                    data[point + y * width * 3 + x * 3 + 2] =
-                        partial_data[y * BLOCK * 3 + x * 3 + 2];
+    let load_time = loaded.saturating_sub(bvh_time);
+    let (world, stats) = active_scene.build_bvh();
+    println!("BVH for the scene: {}", stats);
+    bvh_time += stats.build_time;
+
+    let backdrop = match settings.crop {
+        Some(crop) => match read_backdrop(settings, crop) {
+            Ok(backdrop) => Some(backdrop),
+            Err(e) => exit_with_error(&e),
+        },
+        None => None,
+    };
+    let checkpoint = options.checkpoint_file.as_ref().map(|path| Checkpoint {
+        path: path.clone(),
+        key: match checkpoint_key(&options.scene_file, settings, options.seed) {
+            Ok(key) => key,
+            Err(e) => exit_with_error(&e),
+        },
+        interval: options.checkpoint_interval,
+    });
+    let resume = match &checkpoint {
+        Some(checkpoint) if Path::new(&checkpoint.path).exists() => {
+            match Film::read_checkpoint(
+                &checkpoint.path,
+                checkpoint.key,
+                settings.width,
+                settings.height,
+                settings.filter,
+            ) {
+                Ok((film, samples_done)) => {
+                    println!(
+                        "Resuming from {} at {} samples per pixel",
+                        checkpoint.path, samples_done
+                    );
+                    Some((film, samples_done))
                 }
+                Err(e) => exit_with_error(&e),
             }
         }
-    }
-    println!(
-        "Render time: {:?}",
-        SystemTime::now().duration_since(start).unwrap()
-    );
+        _ => None,
+    };
 
-    image::write_to_png(
-        output_file,
-        data.as_mut_slice(),
-        width as u32,
-        height as u32,
+    let stop = Arc::new(AtomicBool::new(false));
+    catch_interrupt(stop.clone());
+    let write_output = |frame: &Framebuffer| {
+        write_image(
+            &settings.output_file,
+            settings.format,
+            &settings.tone_map,
+            frame,
+            backdrop.as_ref(),
+        )
+    };
+    let checkpoint_file = checkpoint
+        .as_ref()
+        .map(|checkpoint| checkpoint.path.clone());
+    let mut monitor = Monitor::new(write_output, settings.progressive.time_limit, checkpoint);
+    let (frame, render_stats) = render::render_progressive(
+        &active_scene,
+        world,
+        options.n_threads,
+        options.seed,
+        stop.clone(),
+        resume,
+        &mut monitor,
     );
+    monitor.clear();
+    let stopped = stop.load(Ordering::Relaxed);
+    if stopped {
+        println!(
+            "Stopped after {} of {} samples per pixel",
+            render_stats.n_samples, settings.n_samples
+        );
+    }
+    if settings.adaptive.is_some() {
+        let total: u64 = frame.samples.iter().map(|&n| n as u64).sum();
+        println!(
+            "Adaptive sampling: {:.1} samples per pixel on average",
+            total as f64 / frame.samples.len() as f64
+        );
+    }
+    println!("Render time: {:?}", start.elapsed());
+
+    if let Err(e) = write_output(&frame) {
+        exit_with_error(&e);
+    }
+    if let Some(path) = &checkpoint_file {
+        if stopped {
+            println!("Checkpoint saved to {}", path);
+        } else if let Err(why) = fs::remove_file(path) {
+            eprintln!("warning: couldn't remove {}: {}", path, why);
+        }
+    }
+    if let Some(path) = &settings.sample_map {
+        if let Err(e) = write_sample_map(path, &frame, settings.n_samples) {
+            exit_with_error(&e);
+        }
+    }
+
+    let report = Report {
+        scene_file: options.scene_file.clone(),
+        width: settings.width,
+        height: settings.height,
+        n_samples: settings.n_samples,
+        n_threads: options.n_threads,
+        stopped: stopped,
+        load_time: load_time,
+        bvh_time: bvh_time,
+        render: render_stats,
+        total_time: start.elapsed(),
+    };
+    report.print();
+    if let Some(path) = &options.stats_file {
+        if let Err(why) = fs::write(path, report.to_json().pretty(2) + "\n") {
+            exit_with_error(&format!("couldn't write {}: {}", path, why));
+        }
+    }
 }
@@ -1,7 +1,8 @@
-use super::material::Material;
+use super::material::{Material, ScatterSample};
 use super::utils::{reflect, refract};
 use crate::geometry::hittable::HitRecord;
 use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 
 pub struct Dielectric {
     ref_idx: f32,
@@ -15,11 +16,25 @@ impl Dielectric {
 
 fn schlick(cosine: f32, ref_idx: f32) -> f32 {
     let r0 = ((1.0 - ref_idx) / (1.0 + ref_idx)).powf(2.0);
-    r0 + (1.0 - r0 * r0) * (1.0 - cosine).powf(5.0)
+    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+}
+
+fn specular(scattered: Ray, attenuation: Vec3) -> ScatterSample {
+    ScatterSample {
+        scattered: scattered,
+        weight: attenuation,
+        pdf: 0.0,
+        specular: true,
+    }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterSample> {
         let outward_normal: Vec3;
         let reflected = reflect(&r_in.direction, &rec.normal);
 
@@ -39,15 +54,12 @@ impl Material for Dielectric {
             ni_over_nt = 1.0 / self.ref_idx;
             cosine = -Vec3::dot(&r_in.direction, &rec.normal) / r_in.direction.length();
         }
-        match refract(&r_in.direction, &outward_normal, ni_over_nt) {
-            Some(refracted) => {
-                reflect_prob = schlick(cosine, self.ref_idx);
-                if reflect_prob < rand::random::<f32>() {
-                    return Some((attenuation, Ray::new(rec.p, refracted, r_in.time)));
-                }
+        if let Some(refracted) = refract(&r_in.direction, &outward_normal, ni_over_nt) {
+            reflect_prob = schlick(cosine, self.ref_idx);
+            if reflect_prob < sampler.get_1d() {
+                return Some(specular(Ray::new(rec.p, refracted, r_in.time), attenuation));
             }
-            None => {}
         }
-        Some((attenuation, Ray::new(rec.p, reflected, r_in.time)))
+        Some(specular(Ray::new(rec.p, reflected, r_in.time), attenuation))
     }
 }
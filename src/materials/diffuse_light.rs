@@ -1,6 +1,7 @@
-use super::material::Material;
+use super::material::{Material, ScatterSample};
 use crate::geometry::hittable::HitRecord;
 use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 use crate::textures::Texture;
 
 use std::sync::Arc;
@@ -16,11 +17,20 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Vec3, Ray)> {
+    fn sample(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterSample> {
         None
     }
 
     fn emit(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
         self.emit_tex.value(u, v, p)
     }
+
+    fn is_emitter(&self) -> bool {
+        true
+    }
 }
@@ -1,11 +1,14 @@
-use super::material::Material;
+use super::material::{Material, ScatterSample};
 use crate::geometry::hittable::HitRecord;
 use crate::linalg::{Ray, Vec3};
-use crate::random::utils::random_in_unit_sphere;
+use crate::random::sampler::Sampler;
+use crate::random::utils::random_unit_vector;
 use crate::textures::Texture;
 
 use std::sync::Arc;
 
+const UNIFORM_SPHERE_PDF: f32 = 0.25 * std::f32::consts::FRAC_1_PI;
+
 pub struct Isotropic {
     albedo: Arc<dyn Texture>,
 }
@@ -17,10 +20,25 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
-        Some((
-            self.albedo.value(rec.u, rec.v, &rec.p),
-            Ray::new(rec.p, random_in_unit_sphere(), r_in.time),
-        ))
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterSample> {
+        Some(ScatterSample {
+            scattered: Ray::new(rec.p, random_unit_vector(sampler), r_in.time),
+            weight: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: UNIFORM_SPHERE_PDF,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, _direction: &Vec3) -> Vec3 {
+        UNIFORM_SPHERE_PDF * self.albedo.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> f32 {
+        UNIFORM_SPHERE_PDF
     }
 }
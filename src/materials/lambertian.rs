@@ -1,7 +1,8 @@
-use super::material::Material;
+use super::material::{Material, ScatterSample};
 use crate::geometry::hittable::HitRecord;
 use crate::linalg::{Ray, Vec3};
-use crate::random::utils::random_in_unit_sphere;
+use crate::random::sampler::Sampler;
+use crate::random::utils::random_unit_vector;
 use crate::textures::Texture;
 
 use std::sync::Arc;
@@ -16,11 +17,47 @@ impl Lambertian {
     }
 }
 
+/// The normal on the side of the surface the ray came from.
+fn facing_normal(r_in: &Ray, rec: &HitRecord) -> Vec3 {
+    if Vec3::dot(&r_in.direction, &rec.normal) > 0.0 {
+        -1.0 * rec.normal
+    } else {
+        rec.normal
+    }
+}
+
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
-        let target = rec.p + rec.normal + random_in_unit_sphere();
-        let scattered = Ray::new(rec.p, target - rec.p, r_in.time);
-        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        Some((attenuation, scattered))
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterSample> {
+        // A unit vector added to the normal is cosine distributed around it.
+        let normal = facing_normal(r_in, rec);
+        let mut direction = normal + random_unit_vector(sampler);
+        if direction.squared_length() < 1e-12 {
+            direction = normal;
+        }
+        let pdf = self.pdf(r_in, rec, &direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterSample {
+            scattered: Ray::new(rec.p, direction, r_in.time),
+            // The cosine over pi of the BSDF cancels against the pdf.
+            weight: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.pdf(r_in, rec, direction) * self.albedo.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f32 {
+        let cosine = Vec3::dot(&facing_normal(r_in, rec), &Vec3::unit(direction));
+        cosine.max(0.0) * std::f32::consts::FRAC_1_PI
     }
 }
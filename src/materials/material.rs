@@ -1,9 +1,45 @@
 use crate::geometry::hittable::HitRecord;
 use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 
+/// A direction picked by [`Material::sample`].
+pub struct ScatterSample {
+    pub scattered: Ray,
+    /// What light coming back along `scattered` is multiplied by: the BSDF
+    /// times the cosine over `pdf`, or the attenuation of a specular lobe.
+    pub weight: Vec3,
+    /// Density per unit solid angle of the direction, unused when `specular`.
+    pub pdf: f32,
+    /// Picked from a mirror-like lobe that `eval` and `pdf` know nothing about,
+    /// so light sampling can't reach it.
+    pub specular: bool,
+}
+
+/// How light scatters off or is emitted by a surface.
 pub trait Material: Send + Sync {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)>;
+    /// Picks the direction a path continues in, `None` if the ray is absorbed.
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterSample>;
+    /// The BSDF times the cosine at the surface, for light arriving from
+    /// `direction` and leaving back along `r_in`. Black for specular lobes.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    /// Density per unit solid angle with which `sample` picks `direction`,
+    /// zero for specular lobes.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> f32 {
+        0.0
+    }
+    /// Light emitted at the hit point, black for everything but lights.
     fn emit(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+    /// Whether `emit` can be anything but black.
+    fn is_emitter(&self) -> bool {
+        false
+    }
 }
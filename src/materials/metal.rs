@@ -1,7 +1,8 @@
-use super::material::Material;
+use super::material::{Material, ScatterSample};
 use super::utils::reflect;
 use crate::geometry::hittable::HitRecord;
 use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
 use crate::random::utils::random_in_unit_sphere;
 use crate::textures::Texture;
 
@@ -14,12 +15,7 @@ pub struct Metal {
 
 impl Metal {
     pub fn new(albedo: Arc<dyn Texture>, fuzz: f32) -> Self {
-        let clamped_fuzz: f32;
-        if fuzz < 1.0 {
-            clamped_fuzz = fuzz;
-        } else {
-            clamped_fuzz = 1.0;
-        }
+        let clamped_fuzz = if fuzz < 1.0 { fuzz } else { 1.0 };
         Self {
             albedo: albedo,
             fuzz: clamped_fuzz,
@@ -28,16 +24,28 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
+    // Fuzzy reflections count as specular too: the fuzz sphere has no
+    // density that is cheap to evaluate.
+    fn sample(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterSample> {
         let reflected = reflect(&Vec3::unit(&r_in.direction), &rec.normal);
         let scattered = Ray::new(
             rec.p,
-            reflected + self.fuzz * random_in_unit_sphere(),
+            reflected + self.fuzz * random_in_unit_sphere(sampler),
             r_in.time,
         );
         let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
         if Vec3::dot(&scattered.direction, &rec.normal) > 0.0 {
-            Some((attenuation, scattered))
+            Some(ScatterSample {
+                scattered: scattered,
+                weight: attenuation,
+                pdf: 0.0,
+                specular: true,
+            })
         } else {
             None
         }
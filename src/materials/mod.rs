@@ -2,7 +2,7 @@ pub use self::dielectric::Dielectric;
 pub use self::diffuse_light::DiffuseLight;
 pub use self::isotropic::Isotropic;
 pub use self::lambertian::Lambertian;
-pub use self::material::Material;
+pub use self::material::{Material, ScatterSample};
 pub use self::metal::Metal;
 
 mod dielectric;
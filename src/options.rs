@@ -0,0 +1,520 @@
+use raytrace::film::{Filter, FilterKind};
+use raytrace::geometry::bvh_node::BVHSplit;
+use raytrace::random::sampler::SamplerKind;
+use raytrace::render::AdaptiveOptions;
+use raytrace::scene::{ImageFormat, RenderSettings};
+use raytrace::tiles::{Tile, TileOrder};
+use raytrace::tonemap::ToneMapOperator;
+
+use std::fmt;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: raytrace [OPTIONS] SCENE [OUTPUT]
+
+Arguments:
+  SCENE                  Path to the JSON scene file
+  OUTPUT                 Path to the output image (same as --output)
+
+Options:
+      --width <PIXELS>   Image width [default: 512]
+      --height <PIXELS>  Image height [default: 512]
+      --spp <N>          Samples per pixel [default: 250]
+      --threads <N>      Number of worker threads [default: number of CPUs]
+      --max-depth <N>    Maximum number of bounces per path [default: 50]
+      --rr-depth <N>     Bounces before Russian roulette may end a path [default: 3]
+      --seed <N>         Seed of the random numbers of the render [default: 0]
+      --sampler <NAME>   Sample sequence: independent, stratified, halton or sobol
+                         [default: sobol]
+      --filter <NAME>    Pixel filter: box, tent, gaussian, mitchell or
+                         blackman_harris [default: box]
+      --filter-radius <PIXELS>
+                         Radius of the pixel filter [default: depends on the filter]
+      --tile-size <PIXELS>
+                         Side of the tiles handed to worker threads [default: 16]
+      --tile-order <ORDER>
+                         Order tiles are rendered in: scanline, spiral or hilbert
+                         [default: scanline]
+      --crop <X0,Y0,X1,Y1>
+                         Render only the pixels from (X0, Y0) up to (X1, Y1),
+                         counted from the top-left corner, and keep the rest of
+                         the existing output image; fractions of the image
+                         size if any value has a decimal point
+      --checkpoint <FILE>
+                         Save the render to FILE as it goes, and resume from it
+                         if it exists; it is removed once the render completes
+      --checkpoint-every <SECONDS>
+                         Least time between two checkpoints [default: 60]
+      --bvh <BUILDER>    BVH builder, sah or midpoint [default: sah]
+      --leaf-size <N>    Most primitives in a leaf of the SAH BVH [default: 4]
+      --light-sampling <on|off>
+                         Sample lights directly at diffuse bounces [default: on]
+      --pass-spp <N>     Render in passes of N samples per pixel, writing a preview
+                         of the output after each one
+      --preview-every <SECONDS>
+                         Write previews at most this often instead
+      --time-limit <SECONDS>
+                         Stop starting new tiles after this long
+      --adaptive <THRESHOLD>
+                         Stop sampling a pixel once the standard error of its
+                         luminance falls below THRESHOLD times the luminance;
+                         --spp is then the most samples a pixel gets
+      --min-spp <N>      Samples every pixel gets with adaptive sampling, which
+                         --adaptive or the scene turns on [default: 16]
+      --sample-map <FILE>
+                         Also write an image of the samples each pixel got
+      --exposure <STOPS> Exposure applied before tone mapping [default: 0]
+      --tone-map <OPERATOR>
+                         clamp, reinhard, extended-reinhard or aces [default: clamp]
+      --stats-json <FILE>
+                         Also write the statistics printed at the end as JSON
+  -o, --output <FILE>    Path to the output image, whose extension picks the
+                         format: png, ppm, hdr, pfm or exr [default: out.png]
+  -h, --help             Print this help and exit
+
+Ctrl-C or the time limit stop the render early; the output then holds the
+samples taken so far, and the checkpoint, if any, lets a later run finish it.
+
+Width, height, samples, depth, sampler, filter, tiles, crop, BVH, light
+sampling, passes, adaptive sampling, tone mapping and output given here
+override the \"render\" block of the scene file.";
+
+/// A region of the image given with --crop, from the top-left corner, ends excluded.
+pub enum Crop {
+    Pixels([usize; 4]),
+    Fractions([f32; 4]),
+}
+
+impl Crop {
+    fn parse(value: &str) -> Result<Crop, OptionsError> {
+        let error = || invalid(format!("invalid value '{}' for --crop", value));
+        let parts: Vec<&str> = value.split(',').map(|part| part.trim()).collect();
+        if parts.len() != 4 {
+            return error();
+        }
+        let crop = if parts.iter().any(|part| part.contains('.')) {
+            let mut fractions = [0.0; 4];
+            for (fraction, part) in fractions.iter_mut().zip(&parts) {
+                *fraction = match part.parse::<f32>() {
+                    Ok(x) if (0.0..=1.0).contains(&x) => x,
+                    _ => return error(),
+                };
+            }
+            Crop::Fractions(fractions)
+        } else {
+            let mut pixels = [0; 4];
+            for (pixel, part) in pixels.iter_mut().zip(&parts) {
+                *pixel = match part.parse::<usize>() {
+                    Ok(x) => x,
+                    Err(_) => return error(),
+                };
+            }
+            Crop::Pixels(pixels)
+        };
+        let ordered = match &crop {
+            Crop::Pixels(p) => p[0] < p[2] && p[1] < p[3],
+            Crop::Fractions(f) => f[0] < f[2] && f[1] < f[3],
+        };
+        if !ordered {
+            return invalid(String::from(
+                "--crop must end right of and below where it starts",
+            ));
+        }
+        Ok(crop)
+    }
+
+    /// The pixels of a `width` by `height` image the region covers, even partly, with
+    /// `y` going up from the bottom row. It is empty if it lies outside the image.
+    pub fn to_tile(&self, width: usize, height: usize) -> Tile {
+        let [x0, top, x1, bottom] = match self {
+            Crop::Pixels(p) => *p,
+            Crop::Fractions(f) => [
+                (f[0] * width as f32).floor() as usize,
+                (f[1] * height as f32).floor() as usize,
+                (f[2] * width as f32).ceil() as usize,
+                (f[3] * height as f32).ceil() as usize,
+            ],
+        };
+        let (x0, x1) = (x0.min(width), x1.min(width));
+        let (top, bottom) = (top.min(height), bottom.min(height));
+        Tile {
+            x: x0,
+            y: height - bottom,
+            width: x1 - x0,
+            height: bottom - top,
+        }
+    }
+}
+
+pub struct Options {
+    pub scene_file: String,
+    pub output_file: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub n_samples: Option<usize>,
+    pub n_threads: usize,
+    pub max_depth: Option<usize>,
+    pub rr_depth: Option<usize>,
+    pub seed: u64,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<FilterKind>,
+    pub filter_radius: Option<f32>,
+    pub tile_size: Option<usize>,
+    pub tile_order: Option<TileOrder>,
+    pub crop: Option<Crop>,
+    pub checkpoint_file: Option<String>,
+    pub checkpoint_interval: Duration,
+    pub bvh: Option<BVHSplit>,
+    pub leaf_size: Option<usize>,
+    pub light_sampling: Option<bool>,
+    pub pass_samples: Option<usize>,
+    pub preview_interval: Option<Duration>,
+    pub time_limit: Option<Duration>,
+    pub adaptive_threshold: Option<f32>,
+    pub min_samples: Option<usize>,
+    pub sample_map: Option<String>,
+    pub stats_file: Option<String>,
+    pub exposure: Option<f32>,
+    pub tone_map: Option<ToneMapOperator>,
+}
+
+pub enum OptionsError {
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionsError::Help => write!(f, "{}", USAGE),
+            OptionsError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+fn invalid<T>(reason: String) -> Result<T, OptionsError> {
+    Err(OptionsError::Invalid(reason))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, OptionsError> {
+    match value.parse::<T>() {
+        Ok(x) => Ok(x),
+        Err(_) => invalid(format!("invalid value '{}' for --{}", value, name)),
+    }
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize, OptionsError> {
+    let x = parse_number::<usize>(name, value)?;
+    if x == 0 {
+        return invalid(format!("--{} must be greater than zero", name));
+    }
+    Ok(x)
+}
+
+fn parse_seconds(name: &str, value: &str) -> Result<Duration, OptionsError> {
+    let x = parse_number::<f32>(name, value)?;
+    if !x.is_finite() || x < 0.0 {
+        return invalid(format!("invalid value '{}' for --{}", value, name));
+    }
+    Ok(Duration::from_secs_f32(x))
+}
+
+impl Options {
+    /// Parses the command line, `args` not including the program name.
+    pub fn parse(args: &[String]) -> Result<Self, OptionsError> {
+        let mut options = Self {
+            scene_file: String::new(),
+            output_file: None,
+            width: None,
+            height: None,
+            n_samples: None,
+            n_threads: num_cpus::get(),
+            max_depth: None,
+            rr_depth: None,
+            seed: 0,
+            sampler: None,
+            filter: None,
+            filter_radius: None,
+            tile_size: None,
+            tile_order: None,
+            crop: None,
+            checkpoint_file: None,
+            checkpoint_interval: Duration::from_secs(60),
+            bvh: None,
+            leaf_size: None,
+            light_sampling: None,
+            pass_samples: None,
+            preview_interval: None,
+            time_limit: None,
+            adaptive_threshold: None,
+            min_samples: None,
+            sample_map: None,
+            stats_file: None,
+            exposure: None,
+            tone_map: None,
+        };
+        let mut positional = Vec::new();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(OptionsError::Help);
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                positional.push(arg.clone());
+                continue;
+            }
+
+            // Both "--name value" and "--name=value" are accepted.
+            let (name, inline_value) = match arg.find('=') {
+                Some(pos) => (&arg[..pos], Some(String::from(&arg[pos + 1..]))),
+                None => (arg.as_str(), None),
+            };
+            let name = match name {
+                "-o" => "output",
+                _ if name.starts_with("--") => &name[2..],
+                _ => return invalid(format!("unknown option '{}'", name)),
+            };
+            if !matches!(
+                name,
+                "width"
+                    | "height"
+                    | "spp"
+                    | "threads"
+                    | "max-depth"
+                    | "rr-depth"
+                    | "seed"
+                    | "sampler"
+                    | "filter"
+                    | "filter-radius"
+                    | "tile-size"
+                    | "tile-order"
+                    | "crop"
+                    | "checkpoint"
+                    | "checkpoint-every"
+                    | "bvh"
+                    | "leaf-size"
+                    | "light-sampling"
+                    | "pass-spp"
+                    | "preview-every"
+                    | "time-limit"
+                    | "adaptive"
+                    | "min-spp"
+                    | "sample-map"
+                    | "stats-json"
+                    | "exposure"
+                    | "tone-map"
+                    | "output"
+            ) {
+                return invalid(format!("unknown option '--{}'", name));
+            }
+            let value = match inline_value {
+                Some(v) => v,
+                None => match iter.next() {
+                    Some(v) => v.clone(),
+                    None => return invalid(format!("missing value for --{}", name)),
+                },
+            };
+
+            match name {
+                "width" => options.width = Some(parse_positive(name, &value)?),
+                "height" => options.height = Some(parse_positive(name, &value)?),
+                "spp" => options.n_samples = Some(parse_positive(name, &value)?),
+                "threads" => options.n_threads = parse_positive(name, &value)?,
+                "max-depth" => options.max_depth = Some(parse_number(name, &value)?),
+                "rr-depth" => options.rr_depth = Some(parse_number(name, &value)?),
+                "seed" => options.seed = parse_number(name, &value)?,
+                "sampler" => {
+                    options.sampler = match SamplerKind::from_name(&value) {
+                        Some(sampler) => Some(sampler),
+                        None => return invalid(format!("invalid value '{}' for --sampler", value)),
+                    }
+                }
+                "filter" => {
+                    options.filter = match FilterKind::from_name(&value) {
+                        Some(kind) => Some(kind),
+                        None => return invalid(format!("invalid value '{}' for --filter", value)),
+                    }
+                }
+                "filter-radius" => {
+                    let radius = parse_number::<f32>(name, &value)?;
+                    if !radius.is_finite() || radius <= 0.0 {
+                        return invalid(format!("--{} must be greater than zero", name));
+                    }
+                    options.filter_radius = Some(radius);
+                }
+                "tile-size" => options.tile_size = Some(parse_positive(name, &value)?),
+                "tile-order" => {
+                    options.tile_order = match TileOrder::from_name(&value) {
+                        Some(order) => Some(order),
+                        None => {
+                            return invalid(format!("invalid value '{}' for --tile-order", value))
+                        }
+                    }
+                }
+                "crop" => options.crop = Some(Crop::parse(&value)?),
+                "checkpoint" => options.checkpoint_file = Some(value),
+                "checkpoint-every" => options.checkpoint_interval = parse_seconds(name, &value)?,
+                "bvh" => {
+                    options.bvh = match value.as_str() {
+                        "sah" => Some(BVHSplit::SAH),
+                        "midpoint" => Some(BVHSplit::Midpoint),
+                        _ => return invalid(format!("invalid value '{}' for --bvh", value)),
+                    }
+                }
+                "leaf-size" => options.leaf_size = Some(parse_positive(name, &value)?),
+                "light-sampling" => {
+                    options.light_sampling = match value.as_str() {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => {
+                            return invalid(format!(
+                                "invalid value '{}' for --light-sampling",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "pass-spp" => options.pass_samples = Some(parse_positive(name, &value)?),
+                "preview-every" => options.preview_interval = Some(parse_seconds(name, &value)?),
+                "time-limit" => options.time_limit = Some(parse_seconds(name, &value)?),
+                "adaptive" => {
+                    let threshold = parse_number::<f32>(name, &value)?;
+                    if threshold.is_nan() || threshold <= 0.0 {
+                        return invalid(format!("--{} must be greater than zero", name));
+                    }
+                    options.adaptive_threshold = Some(threshold);
+                }
+                "min-spp" => {
+                    let min_samples = parse_number::<usize>(name, &value)?;
+                    if min_samples < 2 {
+                        return invalid(format!("--{} must be at least 2", name));
+                    }
+                    options.min_samples = Some(min_samples);
+                }
+                "sample-map" => options.sample_map = Some(value),
+                "stats-json" => options.stats_file = Some(value),
+                "exposure" => {
+                    let exposure = parse_number::<f32>(name, &value)?;
+                    if !exposure.is_finite() {
+                        return invalid(format!("invalid value '{}' for --{}", value, name));
+                    }
+                    options.exposure = Some(exposure);
+                }
+                "tone-map" => {
+                    options.tone_map = match ToneMapOperator::from_name(&value) {
+                        Some(operator) => Some(operator),
+                        None => {
+                            return invalid(format!("invalid value '{}' for --tone-map", value))
+                        }
+                    }
+                }
+                _ => options.output_file = Some(value),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        match positional.next() {
+            Some(scene_file) => options.scene_file = scene_file,
+            None => return invalid(String::from("missing path to the scene file")),
+        }
+        if let Some(output_file) = positional.next() {
+            if options.output_file.is_some() {
+                return invalid(String::from(
+                    "output file given both as an argument and with --output",
+                ));
+            }
+            options.output_file = Some(output_file);
+        }
+        if let Some(extra) = positional.next() {
+            return invalid(format!("unexpected argument '{}'", extra));
+        }
+
+        Ok(options)
+    }
+
+    /// Replaces the scene's render settings with the ones given on the command line.
+    pub fn override_settings(&self, settings: &mut RenderSettings) {
+        if let Some(width) = self.width {
+            settings.width = width;
+        }
+        if let Some(height) = self.height {
+            settings.height = height;
+        }
+        if let Some(n_samples) = self.n_samples {
+            settings.n_samples = n_samples;
+        }
+        if let Some(max_depth) = self.max_depth {
+            settings.max_depth = max_depth;
+        }
+        if let Some(rr_depth) = self.rr_depth {
+            settings.rr_depth = rr_depth;
+        }
+        if let Some(sampler) = self.sampler {
+            settings.sampler = sampler;
+        }
+        if let Some(kind) = self.filter {
+            settings.filter = Filter::new(kind);
+        }
+        if let Some(radius) = self.filter_radius {
+            settings.filter.radius = radius;
+        }
+        if let Some(tile_size) = self.tile_size {
+            settings.tile_size = tile_size;
+        }
+        if let Some(tile_order) = self.tile_order {
+            settings.tile_order = tile_order;
+        }
+        if let Some(crop) = &self.crop {
+            settings.crop = Some(crop.to_tile(settings.width, settings.height));
+        }
+        if let Some(bvh) = self.bvh {
+            settings.bvh.split = bvh;
+        }
+        if let Some(leaf_size) = self.leaf_size {
+            settings.bvh.max_leaf_size = leaf_size;
+        }
+        if let Some(light_sampling) = self.light_sampling {
+            settings.light_sampling = light_sampling;
+        }
+        if let Some(pass_samples) = self.pass_samples {
+            settings.progressive.pass_samples = Some(pass_samples);
+        }
+        if let Some(preview_interval) = self.preview_interval {
+            settings.progressive.preview_interval = Some(preview_interval);
+        }
+        if let Some(time_limit) = self.time_limit {
+            settings.progressive.time_limit = Some(time_limit);
+        }
+        if let Some(threshold) = self.adaptive_threshold {
+            let adaptive = settings
+                .adaptive
+                .get_or_insert_with(AdaptiveOptions::default);
+            adaptive.threshold = threshold;
+        }
+        // Without adaptive sampling --min-spp is refused once the scene is loaded.
+        if let Some(min_samples) = self.min_samples {
+            if let Some(adaptive) = &mut settings.adaptive {
+                adaptive.min_samples = min_samples;
+            }
+        }
+        if let Some(sample_map) = &self.sample_map {
+            settings.sample_map = Some(sample_map.clone());
+        }
+        if let Some(exposure) = self.exposure {
+            settings.tone_map.exposure = exposure;
+        }
+        if let Some(operator) = self.tone_map {
+            settings.tone_map.operator = operator;
+        }
+        if let Some(output_file) = &self.output_file {
+            settings.output_file = output_file.clone();
+            match (ImageFormat::from_extension(output_file), settings.format) {
+                // A .png or .ppm keeps the variant asked for by the scene file.
+                (Some(ImageFormat::Png), ImageFormat::Png16)
+                | (Some(ImageFormat::Ppm), ImageFormat::PpmAscii) => {}
+                (Some(format), _) => settings.format = format,
+                (None, _) => {}
+            }
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod pcg;
 pub mod perlin;
+pub mod sampler;
 pub mod utils;
@@ -0,0 +1,54 @@
+/// The PCG32 generator (XSH RR): small, fast, and the same sequence on every platform.
+#[derive(Debug, Clone)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+impl Pcg32 {
+    /// A generator starting from `seed` on the stream picked by `sequence`.
+    pub fn new(seed: u64, sequence: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (sequence << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / (1 << 24) as f32)
+    }
+
+    /// Uniform in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u32() as u64 * n as u64) >> 32) as usize
+    }
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Mixes `values` into one well spread number, to seed generators from a pixel,
+/// a sample index and a global seed.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0, |h, &v| {
+        mix(h.wrapping_add(v).wrapping_add(0x9e3779b97f4a7c15))
+    })
+}
@@ -1,8 +1,9 @@
+use super::pcg::Pcg32;
 use crate::linalg::Vec3;
-use rand::Rng;
 
 const PERLIN_SIZE: usize = 256;
 const PERLIN_MASK: usize = PERLIN_SIZE - 1;
+const PERLIN_SEED: u64 = 0x5eed;
 
 pub struct Perlin {
     perm_x: [usize; PERLIN_SIZE],
@@ -24,68 +25,73 @@ fn smooth_lerp(lo: f32, hi: f32, t: f32) -> f32 {
     lerp(lo, hi, smooth_t)
 }
 
-#[allow(dead_code)]
-fn generate_permutation() -> [usize; PERLIN_SIZE] {
+fn generate_permutation(rng: &mut Pcg32) -> [usize; PERLIN_SIZE] {
     let mut p = [0_usize; PERLIN_SIZE];
-    let mut rng = rand::thread_rng();
 
-    for i in 0..PERLIN_SIZE {
-        p[i] = i;
+    for (i, item) in p.iter_mut().enumerate() {
+        *item = i;
     }
 
     for i in 0..PERLIN_SIZE {
-        let j: usize = rng.gen_range(0, 255);
-        p[i] = p[j] + p[i];
+        let j = rng.below(255);
+        p[i] += p[j];
         p[j] = p[i] - p[j];
-        p[i] = p[i] - p[j];
+        p[i] -= p[j];
     }
     p
 }
 
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Perlin {
     pub fn new() -> Self {
         let mut g = [Vec3::new(0.0, 0.0, 0.0); PERLIN_SIZE];
-        let mut rng = rand::thread_rng();
-        for i in 0..PERLIN_SIZE {
-            g[i] = Vec3::unit(&Vec3::new(
-                2.0 * rng.gen::<f32>() - 1.0,
-                2.0 * rng.gen::<f32>() - 1.0,
-                2.0 * rng.gen::<f32>() - 1.0,
+        // A fixed seed, so that the noise looks the same in every render.
+        let mut rng = Pcg32::new(PERLIN_SEED, 0);
+        for item in g.iter_mut() {
+            *item = Vec3::unit(&Vec3::new(
+                2.0 * rng.next_f32() - 1.0,
+                2.0 * rng.next_f32() - 1.0,
+                2.0 * rng.next_f32() - 1.0,
             ));
-            //            g[i] = Vec3::unit(&Vec3::new(rng.gen(), rng.gen(), rng.gen()));
         }
         Self {
             grid: g,
-            perm_x: generate_permutation(),
-            perm_y: generate_permutation(),
-            perm_z: generate_permutation(),
+            perm_x: generate_permutation(&mut rng),
+            perm_y: generate_permutation(&mut rng),
+            perm_z: generate_permutation(&mut rng),
         }
     }
 
+    /// The lattice cell `x` falls in, wrapped around the tables; negative cells wrap too.
+    fn cell(x: f32) -> usize {
+        (x.floor() as i32 & PERLIN_MASK as i32) as usize
+    }
+
     fn hash(&self, x: usize, y: usize, z: usize) -> usize {
         self.perm_x[x] ^ self.perm_y[y] ^ self.perm_z[z]
     }
 
     pub fn noise(&self, p: &Vec3) -> f32 {
-        let xi = (p.0.floor() as usize) & PERLIN_MASK;
+        let xi = Self::cell(p.0);
         let xi1 = (xi + 1) & PERLIN_MASK;
         let fx = p.0 - p.0.floor(); // this is important to not use .fract because it can be negative
         let fxt = fx - 1.0;
 
-        let yi = (p.1.floor() as usize) & PERLIN_MASK;
+        let yi = Self::cell(p.1);
         let yi1 = (yi + 1) & PERLIN_MASK;
         let fy = p.1 - p.1.floor();
         let fyt = fy - 1.0;
 
-        let zi = (p.2.floor() as usize) & PERLIN_MASK;
+        let zi = Self::cell(p.2);
         let zi1 = (zi + 1) & PERLIN_MASK;
         let fz = p.2 - p.2.floor();
         let fzt = fz - 1.0;
 
-        if fx < 0.0 || fy < 0.0 || fz < 0.0 {
-            println!("{} {} {}", fx, fy, fz);
-        }
-
         let c000 = self.grid[self.hash(xi, yi, zi)];
         let c001 = self.grid[self.hash(xi, yi, zi1)];
         let c010 = self.grid[self.hash(xi, yi1, zi)];
@@ -119,3 +125,42 @@ impl Perlin {
         r6 * 0.57 + 0.5
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_does_not_repeat_below_zero() {
+        let perlin = Perlin::new();
+        let axes = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for axis in axes.iter() {
+            let mut repeats = 0;
+            for i in 0..64 {
+                let t = i as f32 * 0.37;
+                let p = Vec3::new(-5.3 + 0.1 * t, -7.6 + 0.2 * t, -2.2 - 0.3 * t);
+                let shifted = p - *axis;
+                if (perlin.noise(&p) - perlin.noise(&shifted)).abs() < 1e-6 {
+                    repeats += 1;
+                }
+            }
+            assert!(
+                repeats < 4,
+                "{} of 64 points repeat one unit apart",
+                repeats
+            );
+        }
+    }
+
+    #[test]
+    fn noise_wraps_around_the_tables() {
+        let perlin = Perlin::new();
+        let p = Vec3::new(-0.3, -12.7, 3.4);
+        let wrapped = p + Vec3::new(PERLIN_SIZE as f32, -(PERLIN_SIZE as f32), 0.0);
+        assert!((perlin.noise(&p) - perlin.noise(&wrapped)).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,305 @@
+//! Sequences the random decisions of every sample are drawn from.
+//!
+//! Each sample of a pixel asks its sampler for numbers in `[0, 1)`, one or two at a
+//! time, always in the same order: the position in the pixel, the lens, the time, then
+//! the choices of every bounce. Each request is a dimension of the sample. Spreading
+//! the samples of a pixel evenly over every dimension, rather than at random, makes
+//! images converge faster.
+
+use super::pcg::{hash, Pcg32};
+
+/// The largest `f32` below 1.
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Hands out the numbers the samples of a pixel are made from.
+pub trait Sampler {
+    /// Starts sample `index` of the pixel at `(x, y)`, from the first dimension.
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64);
+    /// The next dimension of the current sample, in `[0, 1)`.
+    fn get_1d(&mut self) -> f32;
+    /// The next two dimensions of the current sample, spread over the unit square
+    /// together.
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+/// Which sequence a render draws its samples from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    /// Independent uniform numbers.
+    Independent,
+    /// One jittered sample per stratum of every dimension, in a different order for
+    /// every dimension.
+    Stratified,
+    /// The Halton sequence, shifted at random for every pixel.
+    Halton,
+    /// Owen-scrambled Sobol points, shuffled for every pair of dimensions.
+    Sobol,
+}
+
+impl SamplerKind {
+    /// Looks a sampler up by the name used in scene files and on the command line.
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" | "random" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+
+    /// A sampler for a render of `n_samples` samples per pixel.
+    pub fn build(&self, seed: u64, n_samples: usize) -> Box<dyn Sampler> {
+        let pixel = PixelSample::new(seed);
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { pixel: pixel }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {
+                pixel: pixel,
+                n_samples: n_samples.clamp(1, u32::MAX as usize) as u32,
+            }),
+            SamplerKind::Halton => Box::new(HaltonSampler { pixel: pixel }),
+            SamplerKind::Sobol => Box::new(SobolSampler { pixel: pixel }),
+        }
+    }
+}
+
+/// Where the current sample is: pixel, index and next dimension, with a generator
+/// seeded from them for whatever a sequence leaves to chance.
+struct PixelSample {
+    seed: u64,
+    x: u64,
+    y: u64,
+    index: u64,
+    dimension: u64,
+    rng: Pcg32,
+}
+
+impl PixelSample {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed: seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+
+    fn start(&mut self, x: usize, y: usize, index: u64) {
+        self.x = x as u64;
+        self.y = y as u64;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[self.seed, self.x, self.y, index]), 0);
+    }
+
+    /// A number shared by every sample of the pixel in the current dimension, which
+    /// moves on to the next `n` dimensions.
+    fn dimension_hash(&mut self, n: u64) -> u64 {
+        let h = hash(&[self.seed, self.x, self.y, self.dimension]);
+        self.dimension += n;
+        h
+    }
+}
+
+pub struct IndependentSampler {
+    pixel: PixelSample,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.pixel.rng.next_f32()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.pixel.rng.next_f32(), self.pixel.rng.next_f32())
+    }
+}
+
+/// Element `i` of a random permutation of `0..n` picked by `seed`, without building
+/// it (Kensler, "Correlated Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+pub struct StratifiedSampler {
+    pixel: PixelSample,
+    n_samples: u32,
+}
+
+impl StratifiedSampler {
+    /// The stratum of `0..n` the current sample falls in, in the current dimension.
+    fn stratum(&mut self, n: u32, n_dimensions: u64) -> u32 {
+        let seed = self.pixel.dimension_hash(n_dimensions) as u32;
+        permutation_element((self.pixel.index % n as u64) as u32, n, seed)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let n = self.n_samples;
+        let stratum = self.stratum(n, 1);
+        ((stratum as f32 + self.pixel.rng.next_f32()) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        // As square a grid as the sample count allows, some cells staying empty.
+        let nx = (self.n_samples as f32).sqrt().ceil() as u32;
+        let ny = self.n_samples.div_ceil(nx);
+        let stratum = self.stratum(nx * ny, 2);
+        let u = (stratum % nx) as f32 + self.pixel.rng.next_f32();
+        let v = (stratum / nx) as f32 + self.pixel.rng.next_f32();
+        (
+            (u / nx as f32).min(ONE_MINUS_EPSILON),
+            (v / ny as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// The bases of the Halton dimensions; later dimensions are independent numbers.
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// `index` with its digits in `base` mirrored around the point.
+fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut reversed = 0;
+    let mut inv_base_n = 1.0;
+    while index > 0 {
+        reversed = reversed * base + index % base;
+        index /= base;
+        inv_base_n *= inv_base;
+    }
+    reversed as f64 * inv_base_n
+}
+
+pub struct HaltonSampler {
+    pixel: PixelSample,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.pixel.dimension as usize;
+        if dimension >= PRIMES.len() {
+            return self.pixel.rng.next_f32();
+        }
+        // Every pixel shifts the sequence by its own offset, so neighbouring pixels
+        // don't share their error.
+        let offset = (self.pixel.dimension_hash(1) >> 11) as f64 / (1u64 << 53) as f64;
+        let u = radical_inverse(PRIMES[dimension], self.pixel.index) + offset;
+        (u.fract() as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Point `index` of the first (`dimension` 0) or second Sobol dimension, in 32 bits.
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut v = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v = if dimension == 0 { v >> 1 } else { v ^ (v >> 1) };
+    }
+    result
+}
+
+/// Owen scrambling of the reversed bits of `x`, by Laine and Karras' hash.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn to_unit(x: u32) -> f32 {
+    ((x >> 8) as f32 / (1 << 24) as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// Burley's "Practical Hash-based Owen Scrambling": every one or two dimensions take
+/// the first Sobol dimensions, with the sample indices shuffled and the points
+/// scrambled by seeds of their own.
+pub struct SobolSampler {
+    pixel: PixelSample,
+}
+
+impl SobolSampler {
+    /// The shuffled index of the current sample and the seeds scrambling each of its
+    /// `n_dimensions`.
+    fn next_point(&mut self, n_dimensions: u64) -> (u32, [u32; 2]) {
+        let seed = self.pixel.dimension_hash(n_dimensions);
+        let index = nested_uniform_scramble(self.pixel.index as u32, seed as u32);
+        (index, [hash(&[seed, 0]) as u32, hash(&[seed, 1]) as u32])
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (index, seeds) = self.next_point(1);
+        to_unit(nested_uniform_scramble(sobol(index, 0), seeds[0]))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (index, seeds) = self.next_point(2);
+        (
+            to_unit(nested_uniform_scramble(sobol(index, 0), seeds[0])),
+            to_unit(nested_uniform_scramble(sobol(index, 1), seeds[1])),
+        )
+    }
+}
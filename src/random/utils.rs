@@ -1,16 +1,32 @@
+use super::sampler::Sampler;
 use crate::linalg::Vec3;
 
-use rand::Rng;
+/// A point spread uniformly inside the unit sphere, from three dimensions of `sampler`.
+pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let r = sampler.get_1d().cbrt();
+    r * random_unit_vector(sampler)
+}
+
+/// A uniformly distributed direction, from two dimensions of `sampler`.
+pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
+    let z = 1.0 - 2.0 * r1;
+    let phi = 2.0 * std::f32::consts::PI * r2;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    let mut p: Vec3;
-    let mut rng = rand::thread_rng();
+/// A direction spread uniformly over the cone around +z whose half-angle has
+/// cosine `cos_theta_max`.
+pub fn random_in_cone(sampler: &mut dyn Sampler, cos_theta_max: f32) -> Vec3 {
+    let (r1, r2) = sampler.get_2d();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
+    let phi = 2.0 * std::f32::consts::PI * r1;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
 
-    loop {
-        p = 2.0 * Vec3(rng.gen(), rng.gen(), rng.gen()) - Vec3(1.0, 1.0, 1.0);
-        if p.squared_length() < 1.0 {
-            break;
-        }
-    }
-    p
+/// The density per unit solid angle of `random_in_cone`.
+pub fn cone_pdf(cos_theta_max: f32) -> f32 {
+    1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max).max(1e-7))
 }
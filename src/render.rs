@@ -0,0 +1,552 @@
+use crate::backgrounds::Background;
+use crate::camera::Camera;
+use crate::film::{Film, FilmTile};
+use crate::geometry::hittable::{HitRecord, Hittable};
+use crate::linalg::{Ray, Vec3};
+use crate::random::sampler::Sampler;
+use crate::scene::Scene;
+use crate::stats::{self, TraceCounters};
+use crate::tiles::{tiles, Tile};
+use crate::tonemap::ToneMap;
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use threadpool::ThreadPool;
+
+/// Side of the square tiles the image is split into between worker threads, unless
+/// the render settings say otherwise.
+pub const BLOCK: usize = 16;
+
+/// How a render is split into passes, and when it stops early.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ProgressiveOptions {
+    /// Samples per pixel added by each pass. If `None`, all of them are taken in one
+    /// pass, or one at a time when there is a time limit.
+    pub pass_samples: Option<usize>,
+    /// Time between previews of a progressive render, or every pass if `None`.
+    pub preview_interval: Option<Duration>,
+    /// Wall-clock budget after which no new tile is started.
+    pub time_limit: Option<Duration>,
+}
+
+/// Stops sampling pixels once their estimate is good enough. `n_samples` of the
+/// render settings stays the most any pixel gets.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveOptions {
+    /// Standard error of a pixel's luminance, relative to the luminance, below which
+    /// the pixel is done.
+    pub threshold: f32,
+    /// Samples every pixel gets before its error is estimated, at least 2.
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            min_samples: 16,
+        }
+    }
+}
+
+/// How far a render has got.
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// Passes completed so far.
+    pub pass: usize,
+    /// Tiles of the current pass completed so far.
+    pub tiles_done: usize,
+    /// Tiles rendered in the current pass; adaptive sampling may skip some.
+    pub n_tiles: usize,
+    /// Share of the samples per pixel taken so far, between 0 and 1. Adaptive
+    /// sampling may finish before reaching 1.
+    pub fraction: f32,
+    pub elapsed: Duration,
+}
+
+/// Gets told how a render is going.
+pub trait RenderObserver {
+    /// Called whenever a tile is done.
+    fn progress(&mut self, _progress: &Progress) {}
+
+    /// Called with the image so far and its samples per pixel whenever a preview is due.
+    fn preview(&mut self, _frame: &Framebuffer, _n_samples: usize) {}
+
+    /// Called with the film after every tile, and once more with `finished` set when
+    /// the render ends, whether it ran to the end or not. `samples_done` is what the
+    /// passes before the current one took; resuming from `film` and `samples_done`
+    /// carries on from here.
+    fn checkpoint(&mut self, _film: &Film, _samples_done: usize, _finished: bool) {}
+}
+
+impl RenderObserver for () {}
+
+/// What a render did.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RenderStats {
+    /// The most samples per pixel of the passes that were completed.
+    pub n_samples: usize,
+    pub counters: TraceCounters,
+    pub time: Duration,
+}
+
+/// A linear RGB radiance image, stored row by row from the top-left corner.
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+    /// Samples taken for each pixel.
+    pub samples: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width: width,
+            height: height,
+            data: vec![0.0; width * height * 3],
+            samples: vec![0; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        let i = (y * self.width + x) * 3;
+        Vec3::new(self.data[i], self.data[i + 1], self.data[i + 2])
+    }
+
+    /// The image ready for display, 8 bits per channel.
+    pub fn to_rgb8(&self, tone_map: &ToneMap) -> Vec<u8> {
+        tone_map
+            .apply(&self.data)
+            .iter()
+            .map(|&x| (255.99 * x) as u8)
+            .collect()
+    }
+
+    /// The image ready for display, 16 bits per channel.
+    pub fn to_rgb16(&self, tone_map: &ToneMap) -> Vec<u16> {
+        tone_map
+            .apply(&self.data)
+            .iter()
+            .map(|&x| (65535.99 * x) as u16)
+            .collect()
+    }
+}
+
+/// Weight of a sample taken with density `pdf` against another strategy
+/// that would have picked the same direction with density `other_pdf`.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Everything needed to follow a path through the scene, shared by all tiles.
+struct PathTracer {
+    world: Arc<dyn Hittable>,
+    lights: Vec<Arc<dyn Hittable>>,
+    background: Arc<dyn Background>,
+    /// Whether the background is sampled along with `lights`.
+    sample_background: bool,
+    max_depth: usize,
+    rr_depth: usize,
+}
+
+impl PathTracer {
+    /// Number of things light sampling picks from: the lights and maybe the background.
+    fn n_light_choices(&self) -> usize {
+        self.lights.len() + self.sample_background as usize
+    }
+
+    /// Density of picking `direction` from `origin` by choosing one of the
+    /// lights uniformly and then a point on it.
+    fn lights_pdf(&self, origin: &Vec3, direction: &Vec3) -> f32 {
+        let sum: f32 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_towards(origin, direction))
+            .sum();
+        sum / self.n_light_choices() as f32
+    }
+
+    /// Density of picking the background in the unit vector `direction`.
+    fn background_pdf(&self, direction: &Vec3) -> f32 {
+        self.background.pdf(direction) / self.n_light_choices() as f32
+    }
+
+    /// Light arriving at `rec` straight from a randomly picked light, weighted
+    /// against finding the same light by scattering.
+    fn sample_direct(&self, r: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let n = self.n_light_choices();
+        let index = ((sampler.get_1d() * n as f32) as usize).min(n - 1);
+
+        let (direction, emitted, light_pdf) = if index == self.lights.len() {
+            let sample = match self.background.sample(sampler) {
+                Some(sample) => sample,
+                None => return black,
+            };
+            let to_background = Ray::new(rec.p, sample.direction, r.time);
+            stats::record(|c| c.shadow_rays += 1);
+            if self
+                .world
+                .hit(&to_background, 0.001, f32::MAX, sampler)
+                .is_some()
+            {
+                return black;
+            }
+            (
+                sample.direction,
+                self.background.radiance(&sample.direction),
+                self.background_pdf(&sample.direction),
+            )
+        } else {
+            let sample = match self.lights[index].sample_towards(&rec.p, sampler) {
+                Some(sample) => sample,
+                None => return black,
+            };
+            let to_light = Ray::new(rec.p, sample.point - rec.p, r.time);
+            stats::record(|c| c.shadow_rays += 1);
+            // The shadow ray has to reach the sampled point, which sits at t = 1.
+            let hit = match self.world.hit(&to_light, 0.001, 1.001, sampler) {
+                Some(hit) if hit.t > 0.999 => hit,
+                _ => return black,
+            };
+            (
+                to_light.direction,
+                hit.mat.emit(hit.u, hit.v, &hit.p),
+                self.lights_pdf(&rec.p, &to_light.direction),
+            )
+        };
+
+        let scattering_pdf = rec.mat.pdf(r, rec, &direction);
+        if scattering_pdf <= 0.0 || light_pdf <= 0.0 {
+            return black;
+        }
+        rec.mat.eval(r, rec, &direction)
+            * emitted
+            * (power_heuristic(light_pdf, scattering_pdf) / light_pdf)
+    }
+
+    /// Light arriving along `r`, following the path one bounce at a time.
+    fn radiance(&self, r: Ray, sampler: &mut dyn Sampler) -> Vec3 {
+        let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = r;
+        // Density the direction of `ray` was picked with, `None` for camera
+        // rays and specular bounces, whose directions light sampling never produces.
+        let mut scattering_pdf: Option<f32> = None;
+        stats::record(|c| c.camera_rays += 1);
+
+        for depth in 0..=self.max_depth {
+            stats::record(|c| c.path_rays += 1);
+            let rec = match self.world.hit(&ray, 0.001, f32::MAX, sampler) {
+                Some(rec) => rec,
+                None => {
+                    let direction = Vec3::unit(&ray.direction);
+                    let mut background = self.background.radiance(&direction);
+                    if let Some(pdf) = scattering_pdf {
+                        if self.sample_background {
+                            let light_pdf = self.background_pdf(&direction);
+                            background = background * power_heuristic(pdf, light_pdf);
+                        }
+                    }
+                    radiance += throughput * background;
+                    break;
+                }
+            };
+
+            let mut emitted = rec.mat.emit(rec.u, rec.v, &rec.p);
+            if let Some(pdf) = scattering_pdf {
+                if self.n_light_choices() > 0 && rec.mat.is_emitter() {
+                    let light_pdf = self.lights_pdf(&ray.origin, &ray.direction);
+                    emitted = emitted * power_heuristic(pdf, light_pdf);
+                }
+            }
+            radiance += throughput * emitted;
+            if depth == self.max_depth {
+                break;
+            }
+
+            let sample = match rec.mat.sample(&ray, &rec, sampler) {
+                Some(sample) => sample,
+                None => break,
+            };
+            if sample.specular {
+                scattering_pdf = None;
+            } else {
+                if self.n_light_choices() > 0 {
+                    radiance += throughput * self.sample_direct(&ray, &rec, sampler);
+                }
+                scattering_pdf = Some(sample.pdf);
+            }
+            throughput = throughput * sample.weight;
+
+            // Past the first few bounces, paths carrying little light are
+            // stopped at random and the survivors weighted up to make up for it.
+            if depth + 1 >= self.rr_depth {
+                let survival = throughput.0.max(throughput.1).max(throughput.2).min(0.95);
+                if survival <= 0.0 || sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+            ray = sample.scattered;
+        }
+        radiance
+    }
+}
+
+/// Samples for every pixel of `tile` that is `active`, numbered on from the pixel's
+/// `first_samples` up to `target`. `stopped` is polled before every sample.
+///
+/// `sampler` only depends on the pixel and the sample's number, so the image doesn't
+/// depend on threads, tiles or passes.
+fn partial_render(
+    camera: Arc<Camera>,
+    tracer: Arc<PathTracer>,
+    start_x: usize,
+    start_y: usize,
+    width: usize,
+    height: usize,
+    target: u32,
+    sampler: &mut dyn Sampler,
+    picture_width: usize,
+    picture_height: usize,
+    active: &[bool],
+    first_samples: &[u32],
+    stopped: &dyn Fn() -> bool,
+    tile: &mut FilmTile,
+) {
+    let mut pixels = active.iter().zip(first_samples);
+    for y in (start_y..(start_y + height)).rev() {
+        for x in start_x..(start_x + width) {
+            let (&active, &first) = pixels.next().unwrap();
+            let mut count = 0;
+            while active && first + count < target && !stopped() {
+                sampler.start_pixel_sample(x, y, (first + count) as u64);
+                let (r1, r2) = sampler.get_2d();
+                let u = (x as f32 + r1) / (picture_width as f32);
+                let v = (y as f32 + r2) / (picture_height as f32);
+                let r = camera.get_ray(u, v, sampler);
+                tile.add_sample(x, y, (r1, r2), tracer.radiance(r, sampler));
+                count += 1;
+            }
+        }
+    }
+}
+
+/// Renders `scene` with its render settings on `n_threads` worker threads.
+pub fn render(scene: &Scene, n_threads: usize, seed: u64) -> Framebuffer {
+    let (world, _) = scene.build_bvh();
+    render_world(scene, world, n_threads, seed)
+}
+
+/// Like [`render`], with the scene's shapes already put under `world` by [`Scene::build_bvh`].
+pub fn render_world(
+    scene: &Scene,
+    world: Arc<dyn Hittable>,
+    n_threads: usize,
+    seed: u64,
+) -> Framebuffer {
+    let stop = Arc::new(AtomicBool::new(false));
+    render_progressive(scene, world, n_threads, seed, stop, None, &mut ()).0
+}
+
+/// Like [`render_world`], in the passes set by the scene's [`ProgressiveOptions`].
+///
+/// `observer` hears about every tile and gets previews and checkpoints. Setting `stop`,
+/// or running out of time, ends the render within a sample and sets `stop`; the
+/// returned image then averages whatever samples each pixel got. A render given one
+/// of its checkpoints as `resume` only takes the samples that are still missing.
+pub fn render_progressive(
+    scene: &Scene,
+    world: Arc<dyn Hittable>,
+    n_threads: usize,
+    seed: u64,
+    stop: Arc<AtomicBool>,
+    resume: Option<(Film, usize)>,
+    observer: &mut dyn RenderObserver,
+) -> (Framebuffer, RenderStats) {
+    let start = Instant::now();
+    let settings = &scene.render;
+    let width = settings.width;
+    let height = settings.height;
+    let progressive = settings.progressive;
+    let deadline = progressive.time_limit.map(|limit| start + limit);
+    let (mut film, mut samples_done) =
+        resume.unwrap_or_else(|| (Film::new(width, height, settings.filter), 0));
+    let adaptive = settings.adaptive;
+    let sampler_kind = settings.sampler;
+    let total_samples = settings.n_samples;
+    // Adaptive sampling decides which pixels go on between passes. With a time limit
+    // the image has to fill in evenly, or most of it may stay black.
+    let pass_samples = match (progressive.pass_samples, adaptive, progressive.time_limit) {
+        (Some(n), _, _) => n,
+        (None, Some(a), _) => a.min_samples,
+        (None, None, Some(_)) => 1,
+        (None, None, None) => settings.n_samples,
+    };
+
+    let camera = Arc::new(scene.camera.clone());
+    let tracer = Arc::new(PathTracer {
+        world: world,
+        lights: if settings.light_sampling {
+            scene.lights.clone()
+        } else {
+            Vec::new()
+        },
+        background: settings.background.clone(),
+        sample_background: settings.light_sampling && settings.background.is_light(),
+        max_depth: settings.max_depth,
+        rr_depth: settings.rr_depth,
+    });
+
+    // A crop is grown by as far as the filter reaches, so its edge pixels get the
+    // samples from just outside it that a full render would give them too.
+    let region = match settings.crop {
+        Some(crop) => {
+            let margin = settings.filter.margin();
+            let (x0, y0) = (crop.x.saturating_sub(margin), crop.y.saturating_sub(margin));
+            Tile {
+                x: x0,
+                y: y0,
+                width: (crop.x + crop.width + margin).min(width) - x0,
+                height: (crop.y + crop.height + margin).min(height) - y0,
+            }
+        }
+        None => Tile {
+            x: 0,
+            y: 0,
+            width: width,
+            height: height,
+        },
+    };
+    let layout = tiles(&region, settings.tile_size, settings.tile_order);
+    let pool = ThreadPool::new(n_threads);
+    let mut render_stats = RenderStats::default();
+    let mut last_preview = start;
+    let mut pass = 0;
+    while samples_done < settings.n_samples {
+        let n_samples = pass_samples.min(settings.n_samples - samples_done);
+        // Counting up to a target, not by `n_samples`, lets a resumed render finish
+        // the pass it was stopped in.
+        let target = (samples_done + n_samples) as u32;
+        let mut jobs = Vec::new();
+        for &tile in layout.iter() {
+            let active = film.active(
+                tile.x,
+                tile.y,
+                tile.width,
+                tile.height,
+                target,
+                adaptive.as_ref(),
+            );
+            if active.contains(&true) {
+                jobs.push((tile, active));
+            }
+        }
+        let n_tiles = jobs.len();
+        // Tiles overlap where their filters reach, so the sums depend on the order
+        // tiles are added in. That order follows where they are in the image rather
+        // than the tile order or which thread finishes first.
+        let mut by_position: Vec<usize> = (0..n_tiles).collect();
+        by_position.sort_by_key(|&k| (Reverse(jobs[k].0.y), jobs[k].0.x));
+        let mut ranks = vec![0; n_tiles];
+        for (rank, &k) in by_position.iter().enumerate() {
+            ranks[k] = rank;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        for ((tile, active), rank) in jobs.into_iter().zip(ranks) {
+            let first_samples = film.counts(tile.x, tile.y, tile.width, tile.height);
+            let mut film_tile = film.tile(tile.x, tile.y, tile.width, tile.height);
+            let sender = sender.clone();
+            let camera_copy = camera.clone();
+            let tracer_copy = tracer.clone();
+            let stop = stop.clone();
+            pool.execute(move || {
+                // Running out of time stops the other tiles too.
+                let stopped = || {
+                    if deadline.is_some_and(|d| Instant::now() >= d) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    stop.load(Ordering::Relaxed)
+                };
+                if stopped() {
+                    return;
+                }
+                let mut sampler = sampler_kind.build(seed, total_samples);
+                partial_render(
+                    camera_copy,
+                    tracer_copy,
+                    tile.x,
+                    tile.y,
+                    tile.width,
+                    tile.height,
+                    target,
+                    sampler.as_mut(),
+                    width,
+                    height,
+                    &active,
+                    &first_samples,
+                    &stopped,
+                    &mut film_tile,
+                );
+                sender.send((rank, film_tile, stats::take())).unwrap();
+            });
+        }
+        // Skipped tiles drop their sender without sending anything.
+        drop(sender);
+        let mut pending = BTreeMap::new();
+        let mut next_rank = 0;
+        for (tiles_done, (rank, film_tile, counters)) in receiver.iter().enumerate() {
+            pending.insert(rank, film_tile);
+            while let Some(film_tile) = pending.remove(&next_rank) {
+                film.add_tile(&film_tile);
+                next_rank += 1;
+                observer.checkpoint(&film, samples_done, false);
+            }
+            render_stats.counters += counters;
+            let pass_fraction = (tiles_done + 1) as f32 / n_tiles as f32;
+            observer.progress(&Progress {
+                pass: pass,
+                tiles_done: tiles_done + 1,
+                n_tiles: n_tiles,
+                fraction: (samples_done as f32 + n_samples as f32 * pass_fraction)
+                    / settings.n_samples as f32,
+                elapsed: start.elapsed(),
+            });
+        }
+        // Only tiles stopped before they started leave gaps behind them.
+        for film_tile in pending.values() {
+            film.add_tile(film_tile);
+        }
+        if stop.load(Ordering::Relaxed) || n_tiles == 0 {
+            break;
+        }
+        samples_done += n_samples;
+        pass += 1;
+
+        let preview_due = match progressive.preview_interval {
+            None => progressive.pass_samples.is_some(),
+            Some(interval) => last_preview.elapsed() >= interval,
+        };
+        if preview_due && samples_done < settings.n_samples {
+            observer.preview(&film.resolve(), samples_done);
+            last_preview = Instant::now();
+        }
+    }
+    observer.checkpoint(&film, samples_done, true);
+    render_stats.n_samples = samples_done;
+    render_stats.time = start.elapsed();
+    (film.resolve(), render_stats)
+}
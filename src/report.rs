@@ -0,0 +1,215 @@
+use raytrace::film::Film;
+use raytrace::render::{Framebuffer, Progress, RenderObserver, RenderStats};
+
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// Least time between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+fn format_duration(d: Duration) -> String {
+    let seconds = d.as_secs_f64();
+    if seconds < 1.0 {
+        format!("{:.1}ms", seconds * 1e3)
+    } else if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else if seconds < 3600.0 {
+        format!("{}m{:02}s", seconds as u64 / 60, seconds as u64 % 60)
+    } else {
+        format!("{}h{:02}m", seconds as u64 / 3600, seconds as u64 / 60 % 60)
+    }
+}
+
+/// Where the render is saved so that it can be resumed, and how often.
+pub struct Checkpoint {
+    pub path: String,
+    /// Tells the scene and settings the render was started with from any others.
+    pub key: u64,
+    pub interval: Duration,
+}
+
+/// Shows a progress line on stderr while rendering, if it is a terminal, and writes
+/// the previews and checkpoints.
+pub struct Monitor<F: Fn(&Framebuffer) -> Result<(), String>> {
+    write_preview: F,
+    time_limit: Option<Duration>,
+    checkpoint: Option<Checkpoint>,
+    show_progress: bool,
+    last_redraw: Option<Instant>,
+    last_checkpoint: Instant,
+}
+
+impl<F: Fn(&Framebuffer) -> Result<(), String>> Monitor<F> {
+    pub fn new(
+        write_preview: F,
+        time_limit: Option<Duration>,
+        checkpoint: Option<Checkpoint>,
+    ) -> Self {
+        Self {
+            write_preview: write_preview,
+            time_limit: time_limit,
+            checkpoint: checkpoint,
+            show_progress: io::stderr().is_terminal(),
+            last_redraw: None,
+            last_checkpoint: Instant::now(),
+        }
+    }
+
+    /// Removes the progress line, before printing anything else.
+    pub fn clear(&mut self) {
+        if self.show_progress && self.last_redraw.is_some() {
+            eprint!("\r\x1b[K");
+            self.last_redraw = None;
+        }
+    }
+}
+
+impl<F: Fn(&Framebuffer) -> Result<(), String>> RenderObserver for Monitor<F> {
+    fn progress(&mut self, progress: &Progress) {
+        if !self.show_progress {
+            return;
+        }
+        let finished_pass = progress.tiles_done == progress.n_tiles;
+        if !finished_pass
+            && self
+                .last_redraw
+                .is_some_and(|t| t.elapsed() < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        let mut line = format!(
+            "{:5.1}%  pass {}, tile {}/{}",
+            100.0 * progress.fraction,
+            progress.pass + 1,
+            progress.tiles_done,
+            progress.n_tiles
+        );
+        if progress.fraction > 0.0 {
+            let elapsed = progress.elapsed.as_secs_f32();
+            let mut left = elapsed * (1.0 - progress.fraction) / progress.fraction;
+            if let Some(limit) = self.time_limit {
+                left = left.min((limit.as_secs_f32() - elapsed).max(0.0));
+            }
+            line.push_str(&format!(
+                ", ETA {}",
+                format_duration(Duration::from_secs_f32(left))
+            ));
+        }
+        eprint!("\r\x1b[K{}", line);
+        let _ = io::stderr().flush();
+        self.last_redraw = Some(Instant::now());
+    }
+
+    fn preview(&mut self, frame: &Framebuffer, n_samples: usize) {
+        self.clear();
+        match (self.write_preview)(frame) {
+            Ok(()) => println!("Preview at {} samples per pixel", n_samples),
+            Err(e) => eprintln!("warning: {}", e),
+        }
+    }
+
+    fn checkpoint(&mut self, film: &Film, samples_done: usize, finished: bool) {
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => return,
+        };
+        if !finished && self.last_checkpoint.elapsed() < checkpoint.interval {
+            return;
+        }
+        let result = film.write_checkpoint(&checkpoint.path, checkpoint.key, samples_done);
+        self.last_checkpoint = Instant::now();
+        if let Err(e) = result {
+            self.clear();
+            eprintln!("warning: {}", e);
+        }
+    }
+}
+
+/// What a run did, for the summary printed at the end.
+pub struct Report {
+    pub scene_file: String,
+    pub width: usize,
+    pub height: usize,
+    pub n_samples: usize,
+    pub n_threads: usize,
+    /// Whether the render was interrupted or ran out of time.
+    pub stopped: bool,
+    /// Reading the scene, without building the BVHs of its meshes and groups.
+    pub load_time: Duration,
+    /// Building every BVH, the scene's included.
+    pub bvh_time: Duration,
+    pub render: RenderStats,
+    pub total_time: Duration,
+}
+
+fn ratio(a: u64, b: u64) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+impl Report {
+    fn rays_per_second(&self) -> f64 {
+        self.render.counters.rays() as f64 / self.render.time.as_secs_f64().max(1e-9)
+    }
+
+    pub fn print(&self) {
+        let c = &self.render.counters;
+        println!(
+            "Rays: {} ({} camera, {} bounce, {} shadow), {:.2}M rays/s",
+            c.rays(),
+            c.camera_rays,
+            c.path_rays - c.camera_rays,
+            c.shadow_rays,
+            self.rays_per_second() / 1e6
+        );
+        println!(
+            "Average path length: {:.2} segments",
+            ratio(c.path_rays, c.camera_rays)
+        );
+        println!(
+            "BVH per ray: {:.2} node visits, {:.2} primitive tests",
+            ratio(c.bvh_node_visits, c.rays()),
+            ratio(c.primitive_tests, c.rays())
+        );
+        println!(
+            "Time: loading {}, BVH {}, rendering {}, total {}",
+            format_duration(self.load_time),
+            format_duration(self.bvh_time),
+            format_duration(self.render.time),
+            format_duration(self.total_time)
+        );
+    }
+
+    /// The report as JSON, times in seconds.
+    pub fn to_json(&self) -> json::JsonValue {
+        let c = &self.render.counters;
+        json::object! {
+            "scene" => self.scene_file.clone(),
+            "width" => self.width,
+            "height" => self.height,
+            "spp" => self.n_samples,
+            "threads" => self.n_threads,
+            "completed_spp" => self.render.n_samples,
+            "stopped" => self.stopped,
+            "rays" => json::object! {
+                "total" => c.rays(),
+                "camera" => c.camera_rays,
+                "bounce" => c.path_rays - c.camera_rays,
+                "shadow" => c.shadow_rays,
+                "per_second" => self.rays_per_second(),
+            },
+            "average_path_length" => ratio(c.path_rays, c.camera_rays),
+            "bvh_node_visits_per_ray" => ratio(c.bvh_node_visits, c.rays()),
+            "primitive_tests_per_ray" => ratio(c.primitive_tests, c.rays()),
+            "time" => json::object! {
+                "loading" => self.load_time.as_secs_f64(),
+                "bvh" => self.bvh_time.as_secs_f64(),
+                "rendering" => self.render.time.as_secs_f64(),
+                "total" => self.total_time.as_secs_f64(),
+            },
+        }
+    }
+}
@@ -1,202 +1,1406 @@
+use crate::backgrounds::{
+    Background, ConstantBackground, EnvironmentMap, GradientBackground, SkyBackground,
+};
 use crate::camera::Camera;
-use crate::geometry::bvh_node::BVHNode;
+use crate::film::{Filter, FilterKind};
+use crate::geometry::box_object::BoxObject;
+use crate::geometry::bvh_node::{BVHOptions, BVHSplit, BVHStats, BVH};
+use crate::geometry::constant_medium::ConstantMedium;
+use crate::geometry::flip_normals::FlipNormals;
 use crate::geometry::hittable::Hittable;
 use crate::geometry::hittable_list::HittableList;
-use crate::geometry::sphere::Sphere;
-use crate::geometry::triangle::TriangleMesh;
-use crate::linalg::Vec3;
+use crate::geometry::rect::{XYRect, XZRect, YZRect};
+use crate::geometry::sphere::{MovingSphere, Sphere};
+use crate::geometry::transform::{Transform, Translation, YRotation};
+use crate::geometry::triangle::{Triangle, TriangleMesh};
+use crate::image;
+use crate::linalg::{Mat4, Vec3};
 use crate::materials::material::Material;
-use crate::materials::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::materials::{Dielectric, DiffuseLight, Isotropic, Lambertian, Metal};
+use crate::random::sampler::SamplerKind;
+use crate::render::{AdaptiveOptions, ProgressiveOptions, BLOCK};
 use crate::textures::texture::Texture;
-use crate::textures::{CheckerTexture, ConstantTexture};
+use crate::textures::{CheckerTexture, ConstantTexture, ImageTexture, PerlinTexture};
+use crate::tiles::{Tile, TileOrder};
+use crate::tonemap::{ToneMap, ToneMapOperator, TransferFunction};
 
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use std::vec::Vec;
 
+/// A problem found while loading a scene: which file, where in it and what is wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneError {
+    /// The scene or OBJ file, empty for scenes parsed from a string.
+    pub file: String,
+    /// JSON path of the offending value such as `shapes[3].material`, or `line N` in an OBJ file.
+    pub path: String,
+    /// Where the problem is in a file the value at `path` refers to, such as an OBJ mesh.
+    pub inner: Option<Box<SceneError>>,
+    pub reason: String,
+}
+
+impl SceneError {
+    pub fn new(path: &str, reason: String) -> Self {
+        Self {
+            file: String::new(),
+            path: String::from(path),
+            inner: None,
+            reason: reason,
+        }
+    }
+
+    /// This error, found in a file the value at `path` refers to.
+    fn within(self, path: &str) -> Self {
+        Self {
+            file: String::new(),
+            path: String::from(path),
+            reason: self.reason.clone(),
+            inner: Some(Box::new(self)),
+        }
+    }
+
+    fn in_file(mut self, file: &str) -> Self {
+        if self.file.is_empty() {
+            self.file = String::from(file);
+        }
+        self
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}: ", self.file)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        match &self.inner {
+            Some(inner) => write!(f, "{}", inner),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    /// 8-bit PNG.
+    Png,
+    /// 16-bit PNG.
+    Png16,
+    /// 8-bit binary PPM.
+    Ppm,
+    /// 8-bit ASCII PPM.
+    PpmAscii,
+    /// Radiance RGBE, linear.
+    Hdr,
+    /// Portable float map, linear.
+    Pfm,
+    /// Uncompressed scanline OpenEXR with float channels, linear.
+    Exr,
+}
+
+impl ImageFormat {
+    /// Looks a format up by the name used in scene files.
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "png16" => Some(ImageFormat::Png16),
+            "ppm" => Some(ImageFormat::Ppm),
+            "ppm_ascii" => Some(ImageFormat::PpmAscii),
+            "hdr" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+
+    /// Picks the format matching a file's extension, 8-bit for `.png` and binary for `.ppm`.
+    pub fn from_extension(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "png" | "ppm" | "hdr" | "pfm" | "exr" => ImageFormat::from_name(&extension),
+            _ => None,
+        }
+    }
+}
+
+/// Everything about the output image that isn't geometry: the `"render"` block of a scene file.
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub n_samples: usize,
+    /// Hard cap on the number of bounces of a path.
+    pub max_depth: usize,
+    /// Bounces after which paths may be stopped by Russian roulette.
+    pub rr_depth: usize,
+    /// What rays that miss every shape see.
+    pub background: Arc<dyn Background>,
+    pub output_file: String,
+    pub format: ImageFormat,
+    /// How the BVHs over meshes, groups and the whole scene are built.
+    pub bvh: BVHOptions,
+    /// Whether paths also sample the lights directly at every diffuse bounce.
+    pub light_sampling: bool,
+    /// The sequence pixel positions, lens, time and bounces are sampled with.
+    pub sampler: SamplerKind,
+    /// How pixels are reconstructed from the samples around them.
+    pub filter: Filter,
+    /// Side of the square tiles handed to worker threads; edge tiles may be smaller.
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// The only pixels rendered, or the whole image if `None`.
+    pub crop: Option<Tile>,
+    /// How radiance becomes display values in 8 and 16-bit formats.
+    pub tone_map: ToneMap,
+    /// Passes, previews and time budget.
+    pub progressive: ProgressiveOptions,
+    /// Variance-driven sampling, or the same number of samples everywhere if `None`.
+    pub adaptive: Option<AdaptiveOptions>,
+    /// Where to write an image of the number of samples each pixel got.
+    pub sample_map: Option<String>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 512,
+            height: 512,
+            n_samples: 250,
+            max_depth: 50,
+            rr_depth: 3,
+            background: Arc::new(ConstantBackground::new(Vec3::new(0.0, 0.0, 0.0))),
+            output_file: String::from("out.png"),
+            format: ImageFormat::Png,
+            bvh: BVHOptions::default(),
+            light_sampling: true,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::default(),
+            tile_size: BLOCK,
+            tile_order: TileOrder::Scanline,
+            crop: None,
+            tone_map: ToneMap::default(),
+            progressive: ProgressiveOptions::default(),
+            adaptive: None,
+            sample_map: None,
+        }
+    }
+}
+
+/// A camera, the shapes it looks at and the settings to render them with.
 pub struct Scene {
+    pub render: RenderSettings,
     pub camera: Camera,
     pub shapes: Vec<Arc<dyn Hittable>>,
+    /// Every light in `shapes`, found through wrappers, transforms, groups and
+    /// instances, for light sampling. Scenes put together in code list theirs here too.
+    pub lights: Vec<Arc<dyn Hittable>>,
+    /// Named groups of shapes, placed into `shapes` any number of times by cloning the `Arc`.
+    pub groups: HashMap<String, Arc<dyn Hittable>>,
     pub materials: HashMap<String, Arc<dyn Material>>,
     pub textures: HashMap<String, Arc<dyn Texture>>,
+    /// How the BVH of every mesh and group came out, labelled by what it was built for.
+    pub bvh_stats: Vec<(String, BVHStats)>,
+    /// What in the scene file loaded but won't render as well as it could.
+    pub warnings: Vec<String>,
+    fixed_aspect: bool,
 }
 
 impl Scene {
-    pub fn new(path_to_file: &str) -> Self {
-        // world.push(Arc::new(XYRect::new(
-        //     -1.5,
-        //     1.0,
-        //     0.0,
-        //     3.0,
-        //     2.0,
-        //     Arc::new(DiffuseLight::new(Arc::new(ConstantTexture::new(
-        //         1.0 * Vec3::new(1.0, 1.0, 1.0),
-        //     )))),
-        // )));
+    /// An empty scene with default render settings, to be filled in from code.
+    pub fn with_camera(camera: Camera) -> Self {
+        Self {
+            render: RenderSettings::default(),
+            camera: camera,
+            shapes: Vec::new(),
+            lights: Vec::new(),
+            groups: HashMap::new(),
+            materials: HashMap::new(),
+            textures: HashMap::new(),
+            bvh_stats: Vec::new(),
+            warnings: Vec::new(),
+            fixed_aspect: false,
+        }
+    }
+
+    /// Loads a scene from a JSON file.
+    pub fn new(path_to_file: &str) -> Result<Self, SceneError> {
+        Self::new_with_overrides(path_to_file, |_| {})
+    }
+
+    /// Loads a scene from a JSON file, letting `overrides` change the render
+    /// settings before any shape is built.
+    pub fn new_with_overrides<F: Fn(&mut RenderSettings)>(
+        path_to_file: &str,
+        overrides: F,
+    ) -> Result<Self, SceneError> {
         let path = Path::new(path_to_file);
-        let display = path.display();
 
-        let mut file = match File::open(&path) {
-            Err(why) => panic!("couldn't open {}: {}", display, why.to_string()),
+        let mut file = match File::open(path) {
+            Err(why) => {
+                return Err(
+                    SceneError::new("", format!("couldn't open: {}", why)).in_file(path_to_file)
+                )
+            }
             Ok(file) => file,
         };
         let mut source = String::new();
-        file.read_to_string(&mut source).unwrap();
-        let parsed = json::parse(source.as_mut_str()).unwrap();
-
-        let camera = build_camera_from_json(&parsed["camera"]);
-        // dbg!("camera read");
-        let textures = build_textures_from_json(&parsed["textures"]);
-        // dbg!("textures read");
-        let materials = build_materials_from_json(&parsed["materials"], &textures);
-        // dbg!("materials read");
-        let shapes = build_shapes_from_json(&parsed["shapes"], &materials);
-        // dbg!("shapes read");
-        Self {
+        if let Err(why) = file.read_to_string(&mut source) {
+            return Err(
+                SceneError::new("", format!("couldn't read: {}", why)).in_file(path_to_file)
+            );
+        }
+        Self::from_json_with_overrides(&source, overrides).map_err(|e| e.in_file(path_to_file))
+    }
+
+    /// Builds a scene from the text of a JSON scene file.
+    pub fn from_json(source: &str) -> Result<Self, SceneError> {
+        Self::from_json_with_overrides(source, |_| {})
+    }
+
+    pub fn from_json_with_overrides<F: Fn(&mut RenderSettings)>(
+        source: &str,
+        overrides: F,
+    ) -> Result<Self, SceneError> {
+        let parsed = match json::parse(source) {
+            Ok(parsed) => parsed,
+            Err(why) => return Err(SceneError::new("", why.to_string())),
+        };
+        if !parsed.is_object() {
+            return Err(SceneError::new("", String::from("expected a JSON object")));
+        }
+
+        let mut render = build_render_settings_from_json(&parsed["render"])?;
+        overrides(&mut render);
+        let camera = build_camera_from_json(&parsed["camera"], &render)?;
+        let textures = build_textures_from_json(&parsed["textures"])?;
+        let materials = build_materials_from_json(&parsed["materials"], &textures)?;
+        let mut builder = ShapeBuilder::new(&materials, &textures, render.bvh);
+        builder.build_groups(&parsed["groups"])?;
+        let (shapes, lights) = if parsed["shapes"].is_null() {
+            (Vec::new(), Vec::new())
+        } else {
+            builder.build_shapes(&parsed["shapes"], "shapes")?
+        };
+        let groups = builder.groups;
+        let bvh_stats = builder.bvh_stats;
+        // Light sampling is all the warnings are about so far.
+        let warnings = if render.light_sampling {
+            builder.warnings
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            render: render,
             camera: camera,
             shapes: shapes,
+            lights: lights,
+            groups: groups,
             materials: materials,
             textures: textures,
+            bvh_stats: bvh_stats,
+            warnings: warnings,
+            fixed_aspect: !parsed["camera"]["aspect"].is_null(),
+        })
+    }
+
+    /// Makes the camera match the final image resolution. A camera without an
+    /// explicit "aspect" follows the resolution, one with it must agree with it.
+    pub fn fit_camera(&mut self) -> Result<(), String> {
+        let image_aspect = self.render.width as f32 / self.render.height as f32;
+        if (self.camera.aspect - image_aspect).abs() <= 0.001 * image_aspect {
+            return Ok(());
+        }
+        if self.fixed_aspect {
+            return Err(format!(
+                "camera aspect {} does not match the {}x{} image (aspect {:.4})",
+                self.camera.aspect, self.render.width, self.render.height, image_aspect
+            ));
         }
+        self.camera.set_aspect(image_aspect);
+        Ok(())
+    }
+
+    /// Puts every shape of the scene under one BVH, built with the render settings.
+    pub fn build_bvh(&self) -> (Arc<dyn Hittable>, BVHStats) {
+        if self.shapes.is_empty() {
+            return (Arc::new(HittableList::new()), BVHStats::default());
+        }
+        let (bvh, stats) = BVH::new(&self.shapes, 0.0, 1.0, &self.render.bvh);
+        (Arc::new(bvh), stats)
     }
 }
 
-fn build_vector_from_json(data: &json::JsonValue) -> Vec3 {
-    Vec3::new(
-        data[0].as_f32().unwrap(),
-        data[1].as_f32().unwrap(),
-        data[2].as_f32().unwrap(),
-    )
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        String::from(key)
+    } else {
+        format!("{}.{}", path, key)
+    }
 }
 
-fn build_camera_from_json(data: &json::JsonValue) -> Camera {
-    Camera::new(
-        build_vector_from_json(&data["look_from"]),
-        build_vector_from_json(&data["look_at"]),
-        build_vector_from_json(&data["vup"]),
-        data["vfov"].as_f32().unwrap(),
-        data["aspect"].as_f32().unwrap(),
-        data["aperture"].as_f32().unwrap(),
-        data["focus_dist"].as_f32().unwrap(),
-        data["t_open"].as_f32().unwrap(),
-        data["t_close"].as_f32().unwrap(),
+fn field<'a>(
+    data: &'a json::JsonValue,
+    path: &str,
+    key: &str,
+) -> Result<&'a json::JsonValue, SceneError> {
+    let value = &data[key];
+    if value.is_null() {
+        Err(SceneError::new(path, format!("missing \"{}\"", key)))
+    } else {
+        Ok(value)
+    }
+}
+
+fn get_f32(data: &json::JsonValue, path: &str, key: &str) -> Result<f32, SceneError> {
+    match field(data, path, key)?.as_f32() {
+        Some(x) => Ok(x),
+        None => Err(SceneError::new(
+            &join(path, key),
+            String::from("expected a number"),
+        )),
+    }
+}
+
+fn get_usize(data: &json::JsonValue, path: &str, key: &str) -> Result<usize, SceneError> {
+    match field(data, path, key)?.as_usize() {
+        Some(x) => Ok(x),
+        None => Err(SceneError::new(
+            &join(path, key),
+            String::from("expected a non-negative integer"),
+        )),
+    }
+}
+
+fn get_str<'a>(data: &'a json::JsonValue, path: &str, key: &str) -> Result<&'a str, SceneError> {
+    match field(data, path, key)?.as_str() {
+        Some(x) => Ok(x),
+        None => Err(SceneError::new(
+            &join(path, key),
+            String::from("expected a string"),
+        )),
+    }
+}
+
+fn get_vector(data: &json::JsonValue, path: &str, key: &str) -> Result<Vec3, SceneError> {
+    build_vector_from_json(field(data, path, key)?, &join(path, key))
+}
+
+fn lookup<T: ?Sized>(
+    items: &HashMap<String, Arc<T>>,
+    kind: &str,
+    data: &json::JsonValue,
+    path: &str,
+    key: &str,
+) -> Result<Arc<T>, SceneError> {
+    let name = get_str(data, path, key)?;
+    match items.get(name) {
+        Some(item) => Ok(item.clone()),
+        None => Err(SceneError::new(
+            &join(path, key),
+            format!("unknown {} \"{}\"", kind, name),
+        )),
+    }
+}
+
+fn unknown_type(path: &str, kind: &str, t_type: &str, expected: &str) -> SceneError {
+    SceneError::new(
+        &join(path, "type"),
+        format!(
+            "unknown {} type \"{}\" (expected {})",
+            kind, t_type, expected
+        ),
     )
 }
 
-fn build_textures_from_json(data: &json::JsonValue) -> HashMap<String, Arc<dyn Texture>> {
+fn build_vector_from_json(data: &json::JsonValue, path: &str) -> Result<Vec3, SceneError> {
+    if !data.is_array() || data.len() != 3 {
+        return Err(SceneError::new(
+            path,
+            String::from("expected an array of 3 numbers"),
+        ));
+    }
+    match (data[0].as_f32(), data[1].as_f32(), data[2].as_f32()) {
+        (Some(x), Some(y), Some(z)) => Ok(Vec3::new(x, y, z)),
+        _ => Err(SceneError::new(
+            path,
+            String::from("expected an array of 3 numbers"),
+        )),
+    }
+}
+
+fn build_render_settings_from_json(data: &json::JsonValue) -> Result<RenderSettings, SceneError> {
+    let mut settings = RenderSettings::default();
+    if data.is_null() {
+        return Ok(settings);
+    }
+    let path = "render";
+
+    if !data["width"].is_null() {
+        settings.width = get_usize(data, path, "width")?;
+    }
+    if !data["height"].is_null() {
+        settings.height = get_usize(data, path, "height")?;
+    }
+    if settings.width == 0 || settings.height == 0 {
+        return Err(SceneError::new(
+            path,
+            String::from("image size must not be zero"),
+        ));
+    }
+    if !data["spp"].is_null() {
+        settings.n_samples = get_usize(data, path, "spp")?;
+        if settings.n_samples == 0 {
+            return Err(SceneError::new(
+                &join(path, "spp"),
+                String::from("must be greater than zero"),
+            ));
+        }
+    }
+    if !data["max_depth"].is_null() {
+        settings.max_depth = get_usize(data, path, "max_depth")?;
+    }
+    if !data["rr_depth"].is_null() {
+        settings.rr_depth = get_usize(data, path, "rr_depth")?;
+    }
+    if !data["background"].is_null() {
+        settings.background =
+            build_background_from_json(&data["background"], &join(path, "background"))?;
+    }
+    if !data["output"].is_null() {
+        settings.output_file = String::from(get_str(data, path, "output")?);
+    }
+    if !data["format"].is_null() {
+        let name = get_str(data, path, "format")?;
+        settings.format = match ImageFormat::from_name(name) {
+            Some(format) => format,
+            None => {
+                return Err(SceneError::new(
+                    &join(path, "format"),
+                    format!(
+                        "unknown output format \"{}\" (expected png, png16, ppm, ppm_ascii, hdr, pfm or exr)",
+                        name
+                    ),
+                ))
+            }
+        };
+    } else if let Some(format) = ImageFormat::from_extension(&settings.output_file) {
+        settings.format = format;
+    }
+    if !data["pass_spp"].is_null() {
+        let pass_samples = get_usize(data, path, "pass_spp")?;
+        if pass_samples == 0 {
+            return Err(SceneError::new(
+                &join(path, "pass_spp"),
+                String::from("must be greater than zero"),
+            ));
+        }
+        settings.progressive.pass_samples = Some(pass_samples);
+    }
+    if !data["preview_interval"].is_null() {
+        settings.progressive.preview_interval = Some(get_seconds(data, path, "preview_interval")?);
+    }
+    if !data["time_limit"].is_null() {
+        settings.progressive.time_limit = Some(get_seconds(data, path, "time_limit")?);
+    }
+    if !data["adaptive"].is_null() {
+        settings.adaptive = Some(build_adaptive_from_json(
+            &data["adaptive"],
+            &join(path, "adaptive"),
+        )?);
+    }
+    if !data["sample_map"].is_null() {
+        settings.sample_map = Some(String::from(get_str(data, path, "sample_map")?));
+    }
+    if !data["tone_map"].is_null() {
+        settings.tone_map = build_tone_map_from_json(&data["tone_map"], &join(path, "tone_map"))?;
+    }
+    if !data["light_sampling"].is_null() {
+        settings.light_sampling = match data["light_sampling"].as_bool() {
+            Some(x) => x,
+            None => {
+                return Err(SceneError::new(
+                    &join(path, "light_sampling"),
+                    String::from("expected true or false"),
+                ))
+            }
+        };
+    }
+    if !data["sampler"].is_null() {
+        let name = get_str(data, path, "sampler")?;
+        settings.sampler = match SamplerKind::from_name(name) {
+            Some(sampler) => sampler,
+            None => {
+                return Err(SceneError::new(
+                    &join(path, "sampler"),
+                    format!(
+                    "unknown sampler \"{}\" (expected independent, stratified, halton or sobol)",
+                    name
+                ),
+                ))
+            }
+        };
+    }
+    if !data["filter"].is_null() {
+        settings.filter = build_filter_from_json(&data["filter"], &join(path, "filter"))?;
+    }
+    if !data["tile_size"].is_null() {
+        settings.tile_size = get_usize(data, path, "tile_size")?;
+        if settings.tile_size == 0 {
+            return Err(SceneError::new(
+                &join(path, "tile_size"),
+                String::from("must be greater than zero"),
+            ));
+        }
+    }
+    if !data["tile_order"].is_null() {
+        let name = get_str(data, path, "tile_order")?;
+        settings.tile_order = match TileOrder::from_name(name) {
+            Some(order) => order,
+            None => {
+                return Err(SceneError::new(
+                    &join(path, "tile_order"),
+                    format!(
+                        "unknown tile order \"{}\" (expected scanline, spiral or hilbert)",
+                        name
+                    ),
+                ))
+            }
+        };
+    }
+    if !data["bvh"].is_null() {
+        settings.bvh.split = match get_str(data, path, "bvh")? {
+            "sah" => BVHSplit::SAH,
+            "midpoint" => BVHSplit::Midpoint,
+            other => {
+                return Err(SceneError::new(
+                    &join(path, "bvh"),
+                    format!(
+                        "unknown BVH builder \"{}\" (expected sah or midpoint)",
+                        other
+                    ),
+                ))
+            }
+        };
+    }
+    if !data["bvh_leaf_size"].is_null() {
+        settings.bvh.max_leaf_size = get_usize(data, path, "bvh_leaf_size")?;
+        if settings.bvh.max_leaf_size == 0 {
+            return Err(SceneError::new(
+                &join(path, "bvh_leaf_size"),
+                String::from("must be greater than zero"),
+            ));
+        }
+    }
+    Ok(settings)
+}
+
+fn build_adaptive_from_json(
+    data: &json::JsonValue,
+    path: &str,
+) -> Result<AdaptiveOptions, SceneError> {
+    let defaults = AdaptiveOptions::default();
+    let adaptive = AdaptiveOptions {
+        threshold: get_f32_or(data, path, "threshold", defaults.threshold)?,
+        min_samples: if data["min_spp"].is_null() {
+            defaults.min_samples
+        } else {
+            get_usize(data, path, "min_spp")?
+        },
+    };
+    if adaptive.threshold.is_nan() || adaptive.threshold <= 0.0 {
+        return Err(SceneError::new(
+            &join(path, "threshold"),
+            String::from("must be greater than zero"),
+        ));
+    }
+    if adaptive.min_samples < 2 {
+        return Err(SceneError::new(
+            &join(path, "min_spp"),
+            String::from("must be at least 2"),
+        ));
+    }
+    Ok(adaptive)
+}
+
+fn tone_map_operator(name: &str, path: &str) -> Result<ToneMapOperator, SceneError> {
+    match ToneMapOperator::from_name(name) {
+        Some(operator) => Ok(operator),
+        None => Err(SceneError::new(
+            path,
+            format!(
+                "unknown tone mapping operator \"{}\" (expected clamp, reinhard, extended_reinhard or aces)",
+                name
+            ),
+        )),
+    }
+}
+
+fn filter_kind(name: &str, path: &str) -> Result<FilterKind, SceneError> {
+    match FilterKind::from_name(name) {
+        Some(kind) => Ok(kind),
+        None => Err(SceneError::new(
+            path,
+            format!(
+                "unknown filter \"{}\" (expected box, tent, gaussian, mitchell or blackman_harris)",
+                name
+            ),
+        )),
+    }
+}
+
+fn build_filter_from_json(data: &json::JsonValue, path: &str) -> Result<Filter, SceneError> {
+    // A bare string only names the filter.
+    if let Some(name) = data.as_str() {
+        return Ok(Filter::new(filter_kind(name, path)?));
+    }
+    if !data.is_object() {
+        return Err(SceneError::new(
+            path,
+            String::from("expected a filter name or an object"),
+        ));
+    }
+    let mut filter = Filter::new(filter_kind(
+        get_str(data, path, "type")?,
+        &join(path, "type"),
+    )?);
+    if !data["radius"].is_null() {
+        filter.radius = get_f32(data, path, "radius")?;
+        if filter.radius.is_nan() || filter.radius <= 0.0 {
+            return Err(SceneError::new(
+                &join(path, "radius"),
+                String::from("radius must be greater than zero"),
+            ));
+        }
+    }
+    Ok(filter)
+}
+
+fn build_tone_map_from_json(data: &json::JsonValue, path: &str) -> Result<ToneMap, SceneError> {
+    let mut tone_map = ToneMap::default();
+    // A bare string only names the operator.
+    if let Some(name) = data.as_str() {
+        tone_map.operator = tone_map_operator(name, path)?;
+        return Ok(tone_map);
+    }
+    if !data.is_object() {
+        return Err(SceneError::new(
+            path,
+            String::from("expected an operator name or an object"),
+        ));
+    }
+    if !data["operator"].is_null() {
+        let name = get_str(data, path, "operator")?;
+        tone_map.operator = tone_map_operator(name, &join(path, "operator"))?;
+    }
+    tone_map.exposure = get_f32_or(data, path, "exposure", 0.0)?;
+    if !tone_map.exposure.is_finite() {
+        return Err(SceneError::new(
+            &join(path, "exposure"),
+            String::from("expected a finite number of stops"),
+        ));
+    }
+    if !data["white"].is_null() {
+        let white = get_f32(data, path, "white")?;
+        if white <= 0.0 {
+            return Err(SceneError::new(
+                &join(path, "white"),
+                String::from("white point must be greater than zero"),
+            ));
+        }
+        match tone_map.operator {
+            ToneMapOperator::ExtendedReinhard { .. } => {
+                tone_map.operator = ToneMapOperator::ExtendedReinhard { white: Some(white) }
+            }
+            _ => {
+                return Err(SceneError::new(
+                    &join(path, "white"),
+                    String::from("only the extended_reinhard operator has a white point"),
+                ))
+            }
+        }
+    }
+    if !data["gamma"].is_null() {
+        let gamma = get_f32(data, path, "gamma")?;
+        if gamma <= 0.0 {
+            return Err(SceneError::new(
+                &join(path, "gamma"),
+                String::from("gamma must be greater than zero"),
+            ));
+        }
+        tone_map.transfer = TransferFunction::Gamma(gamma);
+    }
+    Ok(tone_map)
+}
+
+fn get_seconds(data: &json::JsonValue, path: &str, key: &str) -> Result<Duration, SceneError> {
+    let seconds = get_f32(data, path, key)?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(SceneError::new(
+            &join(path, key),
+            String::from("expected a number of seconds"),
+        ));
+    }
+    Ok(Duration::from_secs_f32(seconds))
+}
+
+fn get_f32_or(
+    data: &json::JsonValue,
+    path: &str,
+    key: &str,
+    default: f32,
+) -> Result<f32, SceneError> {
+    if data[key].is_null() {
+        Ok(default)
+    } else {
+        get_f32(data, path, key)
+    }
+}
+
+fn get_vector_or(
+    data: &json::JsonValue,
+    path: &str,
+    key: &str,
+    default: Vec3,
+) -> Result<Vec3, SceneError> {
+    if data[key].is_null() {
+        Ok(default)
+    } else {
+        get_vector(data, path, key)
+    }
+}
+
+/// A background is either a colour or an object with a "type".
+fn build_background_from_json(
+    data: &json::JsonValue,
+    path: &str,
+) -> Result<Arc<dyn Background>, SceneError> {
+    if data.is_array() {
+        return Ok(Arc::new(ConstantBackground::new(build_vector_from_json(
+            data, path,
+        )?)));
+    }
+    let background: Arc<dyn Background> = match get_str(data, path, "type")? {
+        "constant" => Arc::new(ConstantBackground::new(get_vector(data, path, "color")?)),
+        "gradient" => Arc::new(GradientBackground::new(
+            get_vector_or(data, path, "bottom", Vec3::new(1.0, 1.0, 1.0))?,
+            get_vector_or(data, path, "top", Vec3::new(0.5, 0.7, 1.0))?,
+        )),
+        "sky" => {
+            let sun_direction = get_vector(data, path, "sun_direction")?;
+            if sun_direction.squared_length() == 0.0 {
+                return Err(SceneError::new(
+                    &join(path, "sun_direction"),
+                    String::from("must not be zero"),
+                ));
+            }
+            let turbidity = get_f32_or(data, path, "turbidity", 3.0)?;
+            if !(1.7..=10.0).contains(&turbidity) {
+                return Err(SceneError::new(
+                    &join(path, "turbidity"),
+                    String::from("must be between 1.7 and 10"),
+                ));
+            }
+            Arc::new(SkyBackground::new(
+                sun_direction,
+                turbidity,
+                get_f32_or(data, path, "intensity", 1.0)?,
+                get_vector_or(data, path, "ground", Vec3::new(0.3, 0.3, 0.3))?,
+                get_f32_or(data, path, "sun_size", 0.53)?,
+                get_vector_or(data, path, "sun_color", Vec3::new(1.0, 0.95, 0.9))?,
+                get_f32_or(data, path, "sun_intensity", 5.0)?,
+            ))
+        }
+        "environment" => {
+            let filename = get_str(data, path, "filename")?;
+            let map = match image::read_image(filename) {
+                Ok(x) => x,
+                Err(why) => return Err(SceneError::new(&join(path, "filename"), why)),
+            };
+            let map = map.into_linear();
+            Arc::new(EnvironmentMap::new(
+                &map.to_rgb(),
+                map.width,
+                map.height,
+                get_f32_or(data, path, "intensity", 1.0)?,
+                get_f32_or(data, path, "rotate_y", 0.0)?,
+            ))
+        }
+        other => {
+            return Err(unknown_type(
+                path,
+                "background",
+                other,
+                "constant, gradient, sky or environment",
+            ))
+        }
+    };
+    Ok(background)
+}
+
+fn build_camera_from_json(
+    data: &json::JsonValue,
+    render: &RenderSettings,
+) -> Result<Camera, SceneError> {
+    let path = "camera";
+    if data.is_null() {
+        return Err(SceneError::new("", String::from("missing \"camera\"")));
+    }
+    // Without an explicit aspect the camera follows the image resolution.
+    let aspect = if data["aspect"].is_null() {
+        render.width as f32 / render.height as f32
+    } else {
+        get_f32(data, path, "aspect")?
+    };
+    Ok(Camera::new(
+        get_vector(data, path, "look_from")?,
+        get_vector(data, path, "look_at")?,
+        get_vector(data, path, "vup")?,
+        get_f32(data, path, "vfov")?,
+        aspect,
+        get_f32(data, path, "aperture")?,
+        get_f32(data, path, "focus_dist")?,
+        get_f32(data, path, "t_open")?,
+        get_f32(data, path, "t_close")?,
+    ))
+}
+
+fn build_textures_from_json(
+    data: &json::JsonValue,
+) -> Result<HashMap<String, Arc<dyn Texture>>, SceneError> {
     let mut textures = HashMap::new();
 
     for (name, texture_data) in data.entries() {
-        let t_type = texture_data["type"].as_str().unwrap();
+        let path = format!("textures.{}", name);
+        let path = path.as_str();
+        let t_type = get_str(texture_data, path, "type")?;
         let texture: Arc<dyn Texture> = match t_type {
-            "constant" => Arc::new(ConstantTexture::new(build_vector_from_json(
-                &texture_data["color"],
-            ))),
+            "constant" => Arc::new(ConstantTexture::new(get_vector(
+                texture_data,
+                path,
+                "color",
+            )?)),
             "checker" => {
-                let odd_name = String::from(texture_data["odd"].as_str().unwrap());
-                let even_name = String::from(texture_data["even"].as_str().unwrap());
-                let odd: &Arc<dyn Texture> = textures.get(&odd_name).unwrap();
-                let even: &Arc<dyn Texture> = textures.get(&even_name).unwrap();
-                Arc::new(CheckerTexture::new(odd.clone(), even.clone()))
+                let odd = lookup(&textures, "texture", texture_data, path, "odd")?;
+                let even = lookup(&textures, "texture", texture_data, path, "even")?;
+                Arc::new(CheckerTexture::new(odd, even))
+            }
+            "image" => {
+                let filename = get_str(texture_data, path, "filename")?;
+                match image::read_image(filename) {
+                    Ok(image) => Arc::new(ImageTexture::new(image.into_linear())),
+                    Err(why) => return Err(SceneError::new(&join(path, "filename"), why)),
+                }
+            }
+            "perlin" | "noise" => {
+                let scale = if texture_data["scale"].is_null() {
+                    1.0
+                } else {
+                    get_f32(texture_data, path, "scale")?
+                };
+                Arc::new(PerlinTexture::new(scale))
+            }
+            _ => {
+                return Err(unknown_type(
+                    path,
+                    "texture",
+                    t_type,
+                    "constant, checker, image or perlin",
+                ))
             }
-            _ => panic!("Unknown texture type"),
         };
         textures.insert(String::from(name), texture);
     }
 
-    textures
+    Ok(textures)
 }
 
 fn build_materials_from_json(
     data: &json::JsonValue,
     textures: &HashMap<String, Arc<dyn Texture>>,
-) -> HashMap<String, Arc<dyn Material>> {
+) -> Result<HashMap<String, Arc<dyn Material>>, SceneError> {
     let mut materials = HashMap::new();
 
     for (name, material_data) in data.entries() {
-        let m_type = material_data["type"].as_str().unwrap();
+        let path = format!("materials.{}", name);
+        let path = path.as_str();
+        let m_type = get_str(material_data, path, "type")?;
         let material: Arc<dyn Material> = match m_type {
-            "dielectric" => Arc::new(Dielectric::new(material_data["ref_idx"].as_f32().unwrap())),
+            "dielectric" => Arc::new(Dielectric::new(get_f32(material_data, path, "ref_idx")?)),
             "diffuse_light" => {
-                let tex_name = String::from(material_data["emit_tex"].as_str().unwrap());
-                let emit_tex: &Arc<dyn Texture> = textures.get(&tex_name).unwrap();
-                Arc::new(DiffuseLight::new(emit_tex.clone()))
+                let emit_tex = lookup(textures, "texture", material_data, path, "emit_tex")?;
+                Arc::new(DiffuseLight::new(emit_tex))
+            }
+            "isotropic" => {
+                let tex = lookup(textures, "texture", material_data, path, "albedo")?;
+                Arc::new(Isotropic::new(tex))
             }
             "lambertian" => {
-                let tex_name = String::from(material_data["albedo"].as_str().unwrap());
-                let tex: &Arc<dyn Texture> = textures.get(&tex_name).unwrap();
-                Arc::new(Lambertian::new(tex.clone()))
+                let tex = lookup(textures, "texture", material_data, path, "albedo")?;
+                Arc::new(Lambertian::new(tex))
             }
             "metal" => {
-                let tex_name = String::from(material_data["albedo"].as_str().unwrap());
-                let fuzz = material_data["fuzz"].as_f32().unwrap();
-                let tex: &Arc<dyn Texture> = textures.get(&tex_name).unwrap();
-                Arc::new(Metal::new(tex.clone(), fuzz))
+                let tex = lookup(textures, "texture", material_data, path, "albedo")?;
+                let fuzz = get_f32(material_data, path, "fuzz")?;
+                Arc::new(Metal::new(tex, fuzz))
+            }
+            _ => {
+                return Err(unknown_type(
+                    path,
+                    "material",
+                    m_type,
+                    "dielectric, diffuse_light, isotropic, lambertian or metal",
+                ))
             }
-            _ => panic!("Unknown material type"),
         };
         materials.insert(String::from(name), material);
     }
 
-    materials
+    Ok(materials)
 }
 
-fn build_shapes_from_json(
-    data: &json::JsonValue,
-    materials: &HashMap<String, Arc<dyn Material>>,
-) -> Vec<Arc<dyn Hittable>> {
-    let mut shapes = Vec::new();
-    for shape_data in data.members() {
-        let s_type = shape_data["type"].as_str().unwrap();
+/// The lights inside a shape, each wrapped the way the shape wraps it.
+type Lights = Vec<Arc<dyn Hittable>>;
+/// A shape and its lights.
+type Built = (Arc<dyn Hittable>, Lights);
+
+/// Puts every light of `lights` under the wrapper `wrap`.
+fn wrap_lights<F: Fn(Arc<dyn Hittable>) -> Arc<dyn Hittable>>(lights: Lights, wrap: F) -> Lights {
+    lights.into_iter().map(wrap).collect()
+}
+
+/// Builds shapes, keeping every group and mesh around so instances share them.
+struct ShapeBuilder<'a> {
+    materials: &'a HashMap<String, Arc<dyn Material>>,
+    textures: &'a HashMap<String, Arc<dyn Texture>>,
+    groups: HashMap<String, Arc<dyn Hittable>>,
+    /// The lights of every group, placed along with each instance.
+    group_lights: HashMap<String, Lights>,
+    meshes: HashMap<(String, String), Built>,
+    bvh: BVHOptions,
+    bvh_stats: Vec<(String, BVHStats)>,
+    warnings: Vec<String>,
+}
+
+impl<'a> ShapeBuilder<'a> {
+    fn new(
+        materials: &'a HashMap<String, Arc<dyn Material>>,
+        textures: &'a HashMap<String, Arc<dyn Texture>>,
+        bvh: BVHOptions,
+    ) -> Self {
+        Self {
+            materials: materials,
+            textures: textures,
+            groups: HashMap::new(),
+            group_lights: HashMap::new(),
+            meshes: HashMap::new(),
+            bvh: bvh,
+            bvh_stats: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Puts `children` under one BVH, shared by every instance of a group or mesh.
+    fn build_node(
+        &mut self,
+        mut children: Vec<Arc<dyn Hittable>>,
+        label: String,
+    ) -> Arc<dyn Hittable> {
+        if children.len() == 1 {
+            return children.pop().unwrap();
+        }
+        let (node, stats) = BVH::new(&children, 0.0, 1.0, &self.bvh);
+        self.bvh_stats.push((label, stats));
+        Arc::new(node)
+    }
+
+    fn build_groups(&mut self, data: &json::JsonValue) -> Result<(), SceneError> {
+        for (name, group_data) in data.entries() {
+            let path = format!("groups.{}", name);
+            let path = path.as_str();
+            let (children, lights) =
+                self.build_shapes(field(group_data, path, "shapes")?, &join(path, "shapes"))?;
+            if children.is_empty() {
+                return Err(SceneError::new(
+                    path,
+                    String::from("the group has no shapes"),
+                ));
+            }
+            let node = self.build_node(children, format!("group {}", name));
+            let (group, lights) = apply_transform((node, lights), group_data, path)?;
+            self.groups.insert(String::from(name), group);
+            self.group_lights.insert(String::from(name), lights);
+        }
+        Ok(())
+    }
+
+    fn build_shapes(
+        &mut self,
+        data: &json::JsonValue,
+        path: &str,
+    ) -> Result<(Vec<Arc<dyn Hittable>>, Lights), SceneError> {
+        if !data.is_array() {
+            return Err(SceneError::new(
+                path,
+                String::from("expected an array of shapes"),
+            ));
+        }
+        let mut shapes = Vec::new();
+        let mut lights = Vec::new();
+        for (i, shape_data) in data.members().enumerate() {
+            let shape_path = format!("{}[{}]", path, i);
+            let (shape, shape_lights) = self.build_shape(shape_data, &shape_path)?;
+            shapes.push(shape);
+            lights.extend(shape_lights);
+        }
+        Ok((shapes, lights))
+    }
+
+    fn build_shape(
+        &mut self,
+        shape_data: &json::JsonValue,
+        path: &str,
+    ) -> Result<Built, SceneError> {
+        let materials = self.materials;
+        let material = || lookup(materials, "material", shape_data, path, "material");
+        // Left as `None` for shapes that are a light themselves or hold none.
+        let mut lights = None;
+
+        let s_type = get_str(shape_data, path, "type")?;
         let shape: Arc<dyn Hittable> = match s_type {
-            "sphere" => {
-                let mat_name = String::from(shape_data["material"].as_str().unwrap());
-                let mat: &Arc<dyn Material> = materials.get(&mat_name).unwrap();
-                Arc::new(Sphere::new(
-                    build_vector_from_json(&shape_data["center"]),
-                    shape_data["radius"].as_f32().unwrap(),
-                    mat.clone(),
+            "sphere" => Arc::new(Sphere::new(
+                get_vector(shape_data, path, "center")?,
+                get_f32(shape_data, path, "radius")?,
+                material()?,
+            )),
+            "moving_sphere" => {
+                let sphere_material = material()?;
+                if sphere_material.is_emitter() {
+                    self.warnings.push(format!(
+                        "{}: a moving_sphere that emits light is only found by rays that \
+                         bounce into it, not by light sampling",
+                        path
+                    ));
+                }
+                Arc::new(MovingSphere::new(
+                    get_vector(shape_data, path, "center_start")?,
+                    get_vector(shape_data, path, "center_end")?,
+                    get_f32(shape_data, path, "time_start")?,
+                    get_f32(shape_data, path, "time_end")?,
+                    get_f32(shape_data, path, "radius")?,
+                    sphere_material,
                 ))
             }
-            // "triangle" => {
-            //     let mat_name = String::from(shape_data["material"].as_str().unwrap());
-            //     let mat: &Arc<Material> = materials.get(&mat_name).unwrap();
-            //     Arc::new(Triangle::new(
-            //         &build_vector_from_json(&shape_data["vertices"][0]),
-            //         &build_vector_from_json(&shape_data["vertices"][1]),
-            //         &build_vector_from_json(&shape_data["vertices"][2]),
-            //         mat.clone(),
-            //     ))
-            // }
+            "xy_rect" => Arc::new(XYRect::new(
+                get_f32(shape_data, path, "x0")?,
+                get_f32(shape_data, path, "y0")?,
+                get_f32(shape_data, path, "x1")?,
+                get_f32(shape_data, path, "y1")?,
+                get_f32(shape_data, path, "k")?,
+                material()?,
+            )),
+            "xz_rect" => Arc::new(XZRect::new(
+                get_f32(shape_data, path, "x0")?,
+                get_f32(shape_data, path, "z0")?,
+                get_f32(shape_data, path, "x1")?,
+                get_f32(shape_data, path, "z1")?,
+                get_f32(shape_data, path, "k")?,
+                material()?,
+            )),
+            "yz_rect" => Arc::new(YZRect::new(
+                get_f32(shape_data, path, "y0")?,
+                get_f32(shape_data, path, "z0")?,
+                get_f32(shape_data, path, "y1")?,
+                get_f32(shape_data, path, "z1")?,
+                get_f32(shape_data, path, "k")?,
+                material()?,
+            )),
+            "box" => Arc::new(BoxObject::new(
+                get_vector(shape_data, path, "min")?,
+                get_vector(shape_data, path, "max")?,
+                material()?,
+            )),
+            "triangle" => build_triangle_from_json(shape_data, path, material()?)?,
             "triangle_mesh" => {
-                let mat_name = String::from(shape_data["material"].as_str().unwrap());
-                let mat: &Arc<dyn Material> = materials.get(&mat_name).unwrap();
-                let triangle_mesh = build_triangle_mesh_from_obj(
-                    &shape_data["filename"].as_str().unwrap(),
-                    mat.clone(),
+                let filename = get_str(shape_data, path, "filename")?;
+                let key = (
+                    String::from(filename),
+                    String::from(get_str(shape_data, path, "material")?),
                 );
-                let mut hl = HittableList::new();
-                for t in triangle_mesh.iter() {
-                    hl.push(Arc::new(t));
+                match self.meshes.get(&key) {
+                    Some((mesh, mesh_lights)) => {
+                        lights = Some(mesh_lights.clone());
+                        mesh.clone()
+                    }
+                    None => {
+                        let triangle_mesh = build_triangle_mesh_from_obj(filename, material()?)
+                            .map_err(|e| e.within(path))?;
+                        let mut triangles: Vec<Arc<dyn Hittable>> = Vec::new();
+                        for t in triangle_mesh.iter() {
+                            triangles.push(Arc::new(t));
+                        }
+                        if triangles.is_empty() {
+                            return Err(SceneError::new(
+                                &join(path, "filename"),
+                                String::from("the mesh has no faces"),
+                            ));
+                        }
+                        // Every triangle of a glowing mesh is a light of its own.
+                        let mesh_lights: Lights = triangles
+                            .iter()
+                            .filter(|triangle| triangle.is_light())
+                            .cloned()
+                            .collect();
+                        let mesh = self.build_node(triangles, format!("mesh {}", filename));
+                        self.meshes.insert(key, (mesh.clone(), mesh_lights.clone()));
+                        lights = Some(mesh_lights);
+                        mesh
+                    }
                 }
-                Arc::new(BVHNode::new(hl.list.as_mut_slice(), 0.0, 1.0))
             }
-            _ => panic!("Unknown shape type"),
+            "instance" => {
+                let name = get_str(shape_data, path, "group")?;
+                match self.groups.get(name) {
+                    Some(group) => {
+                        lights = Some(self.group_lights[name].clone());
+                        group.clone()
+                    }
+                    None => {
+                        return Err(SceneError::new(
+                            &join(path, "group"),
+                            format!("unknown group \"{}\"", name),
+                        ))
+                    }
+                }
+            }
+            "constant_medium" => Arc::new(ConstantMedium::new(
+                self.build_inner(shape_data, path, "boundary")?.0,
+                get_f32(shape_data, path, "density")?,
+                lookup(self.textures, "texture", shape_data, path, "albedo")?,
+            )),
+            "flip_normals" => {
+                let (inner, inner_lights) = self.build_inner(shape_data, path, "shape")?;
+                lights = Some(wrap_lights(inner_lights, |light| {
+                    Arc::new(FlipNormals::new(light))
+                }));
+                Arc::new(FlipNormals::new(inner))
+            }
+            "translate" => {
+                let (inner, inner_lights) = self.build_inner(shape_data, path, "shape")?;
+                let offset = get_vector(shape_data, path, "offset")?;
+                lights = Some(wrap_lights(inner_lights, |light| {
+                    Arc::new(Translation::new(light, offset))
+                }));
+                Arc::new(Translation::new(inner, offset))
+            }
+            "rotate_y" => {
+                let (inner, inner_lights) = self.build_inner(shape_data, path, "shape")?;
+                let angle = get_f32(shape_data, path, "angle")?;
+                lights = Some(wrap_lights(inner_lights, |light| {
+                    Arc::new(YRotation::new(light, angle))
+                }));
+                Arc::new(YRotation::new(inner, angle))
+            }
+            _ => {
+                return Err(unknown_type(
+                    path,
+                    "shape",
+                    s_type,
+                    "sphere, moving_sphere, xy_rect, xz_rect, yz_rect, box, triangle, \
+                     triangle_mesh, instance, constant_medium, flip_normals, translate or rotate_y",
+                ))
+            }
         };
-        shapes.push(shape);
+        let lights = lights.unwrap_or_else(|| {
+            if shape.is_light() {
+                vec![shape.clone()]
+            } else {
+                Vec::new()
+            }
+        });
+        apply_transform((shape, lights), shape_data, path)
+    }
+
+    // Wrappers such as "translate" hold the shape they act on under "shape".
+    fn build_inner(
+        &mut self,
+        shape_data: &json::JsonValue,
+        path: &str,
+        key: &str,
+    ) -> Result<Built, SceneError> {
+        let inner_data = field(shape_data, path, key)?;
+        self.build_shape(inner_data, &join(path, key))
     }
-    shapes
 }
 
-fn build_triangle_mesh_from_obj(path_to_file: &str, material: Arc<dyn Material>) -> TriangleMesh {
+fn build_matrix_from_json(data: &json::JsonValue, path: &str) -> Result<Mat4, SceneError> {
+    let numbers: Vec<Option<f32>> = if data.len() == 4 && data.members().all(|r| r.is_array()) {
+        data.members()
+            .flat_map(|row| row.members().map(|x| x.as_f32()).collect::<Vec<_>>())
+            .collect()
+    } else {
+        data.members().map(|x| x.as_f32()).collect()
+    };
+    if !data.is_array() || numbers.len() != 16 || numbers.iter().any(|x| x.is_none()) {
+        return Err(SceneError::new(
+            path,
+            String::from("expected 4 rows of 4 numbers or 16 numbers"),
+        ));
+    }
+    let mut m = [[0.0; 4]; 4];
+    for (i, x) in numbers.into_iter().enumerate() {
+        m[i / 4][i % 4] = x.unwrap();
+    }
+    Ok(Mat4(m))
+}
+
+/// The optional transform every shape, group and instance may carry. The parts are
+/// applied in the order "matrix", "scale", "rotate", "rotate_y" and "translate".
+fn build_transform_from_json(
+    data: &json::JsonValue,
+    path: &str,
+) -> Result<Option<Mat4>, SceneError> {
+    let mut transform = None;
+    let mut then = |m: Mat4| {
+        transform = Some(match transform {
+            Some(t) => m * t,
+            None => m,
+        })
+    };
+
+    if !data["matrix"].is_null() {
+        then(build_matrix_from_json(
+            &data["matrix"],
+            &join(path, "matrix"),
+        )?);
+    }
+    if !data["scale"].is_null() {
+        let factors = match data["scale"].as_f32() {
+            Some(s) => Vec3::new(s, s, s),
+            None => get_vector(data, path, "scale")?,
+        };
+        then(Mat4::scaling(&factors));
+    }
+    if !data["rotate"].is_null() {
+        let rotate = &data["rotate"];
+        let rotate_path = join(path, "rotate");
+        if rotate.is_object() {
+            let axis = get_vector(rotate, &rotate_path, "axis")?;
+            if axis.squared_length() == 0.0 {
+                return Err(SceneError::new(
+                    &join(&rotate_path, "axis"),
+                    String::from("the rotation axis must not be zero"),
+                ));
+            }
+            then(Mat4::rotation(
+                &axis,
+                get_f32(rotate, &rotate_path, "angle")?,
+            ));
+        } else {
+            then(Mat4::euler_rotation(&build_vector_from_json(
+                rotate,
+                &rotate_path,
+            )?));
+        }
+    }
+    if !data["rotate_y"].is_null() {
+        then(Mat4::rotation(
+            &Vec3::new(0.0, 1.0, 0.0),
+            get_f32(data, path, "rotate_y")?,
+        ));
+    }
+    if !data["translate"].is_null() {
+        then(Mat4::translation(&get_vector(data, path, "translate")?));
+    }
+
+    if let Some(m) = transform {
+        if m.inverse().is_none() {
+            return Err(SceneError::new(
+                path,
+                String::from("the transform is not invertible"),
+            ));
+        }
+    }
+    Ok(transform)
+}
+
+fn apply_transform(
+    (shape, lights): Built,
+    data: &json::JsonValue,
+    path: &str,
+) -> Result<Built, SceneError> {
+    match build_transform_from_json(data, path)? {
+        Some(matrix) => Ok((
+            Arc::new(Transform::new(shape, matrix)),
+            wrap_lights(lights, |light| Arc::new(Transform::new(light, matrix))),
+        )),
+        None => Ok((shape, lights)),
+    }
+}
+
+fn build_triangle_from_json(
+    shape_data: &json::JsonValue,
+    path: &str,
+    material: Arc<dyn Material>,
+) -> Result<Arc<dyn Hittable>, SceneError> {
+    let corners = |key: &str| -> Result<Vec<Vec3>, SceneError> {
+        let data = field(shape_data, path, key)?;
+        let key_path = join(path, key);
+        if !data.is_array() || data.len() != 3 {
+            return Err(SceneError::new(
+                &key_path,
+                String::from("expected an array of 3 vectors"),
+            ));
+        }
+        data.members()
+            .enumerate()
+            .map(|(i, v)| build_vector_from_json(v, &format!("{}[{}]", key_path, i)))
+            .collect()
+    };
+
+    let vertices = corners("vertices")?;
+    // Without per-vertex normals the face normal follows the counter-clockwise winding.
+    let normals = if shape_data["normals"].is_null() {
+        let normal = Vec3::unit(&Vec3::cross(
+            &(vertices[1] - vertices[0]),
+            &(vertices[2] - vertices[0]),
+        ));
+        vec![normal, normal, normal]
+    } else {
+        corners("normals")?.iter().map(Vec3::unit).collect()
+    };
+    Ok(Arc::new(Triangle::new(
+        0,
+        1,
+        2,
+        0,
+        1,
+        2,
+        Arc::new(vertices),
+        Arc::new(normals),
+        material,
+    )))
+}
+
+/// Reads the vertices, normals and triangular faces of an OBJ file.
+pub fn build_triangle_mesh_from_obj(
+    path_to_file: &str,
+    material: Arc<dyn Material>,
+) -> Result<TriangleMesh, SceneError> {
     let path = Path::new(path_to_file);
-    let display = path.display();
 
-    let file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", display, why.to_string()),
+    let file = match File::open(path) {
+        Err(why) => {
+            return Err(SceneError::new("", format!("couldn't open: {}", why)).in_file(path_to_file))
+        }
         Ok(file) => file,
     };
 
@@ -206,65 +1410,79 @@ fn build_triangle_mesh_from_obj(path_to_file: &str, material: Arc<dyn Material>)
     let mut n_index = Vec::new();
     let mut n_triangles = 0;
 
-    for line in BufReader::new(file).lines() {
-        let l = line.unwrap();
-        if l.starts_with("v ") {
-            let coord: Vec<f32> = l
-                .split(" ")
-                .skip(1)
-                .filter(|v| !v.is_empty())
-                .map(|v| v.parse::<f32>().unwrap())
-                .collect();
-            vertices.push(Vec3::new(coord[0], coord[1], coord[2]));
-        } else if l.starts_with("vn") {
-            let coord: Vec<f32> = l
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let location = format!("line {}", number + 1);
+        let error = |reason: String| SceneError::new(&location, reason).in_file(path_to_file);
+        let l = match line {
+            Ok(l) => l,
+            Err(why) => return Err(error(format!("couldn't read: {}", why))),
+        };
+        if l.starts_with("v ") || l.starts_with("vn") {
+            let coord: Result<Vec<f32>, _> = l
                 .split(" ")
                 .skip(1)
                 .filter(|v| !v.is_empty())
-                .map(|v| v.parse::<f32>().unwrap())
+                .map(|v| v.parse::<f32>())
                 .collect();
-            normals.push(Vec3::new(coord[0], coord[1], coord[2]));
+            let coord = match coord {
+                Ok(ref c) if c.len() >= 3 => Vec3::new(c[0], c[1], c[2]),
+                _ => return Err(error(String::from("expected 3 coordinates"))),
+            };
+            if l.starts_with("v ") {
+                vertices.push(coord);
+            } else {
+                normals.push(coord);
+            }
         } else if l.starts_with("f ") {
-            for (v, _, n) in l
-                .split(" ")
-                .skip(1)
-                .filter(|v| !v.is_empty())
-                .map(parse_face_fragment)
-            {
+            let fragments: Vec<&str> = l.split(" ").skip(1).filter(|v| !v.is_empty()).collect();
+            if fragments.len() != 3 {
+                return Err(error(format!(
+                    "only triangles are supported, found a face with {} vertices",
+                    fragments.len()
+                )));
+            }
+            for fragment in fragments {
+                let (v, _, n) = match parse_face_fragment(fragment) {
+                    Some(indices) => indices,
+                    None => return Err(error(format!("malformed face vertex \"{}\"", fragment))),
+                };
+                if v == 0 || v > vertices.len() {
+                    return Err(error(format!("vertex index {} is out of range", v)));
+                }
+                if n == 0 || n > normals.len() {
+                    return Err(error(format!("normal index {} is out of range", n)));
+                }
                 v_index.push(v);
                 n_index.push(n);
             }
             n_triangles += 1;
         }
     }
-    TriangleMesh::new(
+    Ok(TriangleMesh::new(
         n_triangles,
         Arc::new(vertices),
         Arc::new(normals),
         v_index,
         n_index,
         material.clone(),
-    )
+    ))
 }
 
-fn parse_face_fragment(s: &str) -> (usize, usize, usize) {
+fn parse_face_fragment(s: &str) -> Option<(usize, usize, usize)> {
     let parts: Vec<&str> = s.split("/").collect();
     match parts.len() {
         1 => {
-            let v = parts[0].parse::<usize>().unwrap();
-            (v, 0, v)
+            let v = parts[0].parse::<usize>().ok()?;
+            Some((v, 0, v))
         }
         3 => {
-            let tc = match parts[1].parse::<usize>() {
-                Ok(x) => x,
-                Err(_) => 0,
-            };
-            (
-                parts[0].parse::<usize>().unwrap(),
+            let tc = parts[1].parse::<usize>().unwrap_or_default();
+            Some((
+                parts[0].parse::<usize>().ok()?,
                 tc,
-                parts[2].parse::<usize>().unwrap(),
-            )
+                parts[2].parse::<usize>().ok()?,
+            ))
         }
-        _ => panic!("unknown format"),
+        _ => None,
     }
 }
@@ -0,0 +1,51 @@
+//! Counters of the work done while tracing rays.
+
+use std::cell::Cell;
+use std::ops::AddAssign;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TraceCounters {
+    /// Rays leaving the camera, one per sample.
+    pub camera_rays: u64,
+    /// Segments of paths, camera rays included.
+    pub path_rays: u64,
+    /// Rays only checking whether a light is visible.
+    pub shadow_rays: u64,
+    pub bvh_node_visits: u64,
+    /// Shapes found in BVH leaves and tested against a ray.
+    pub primitive_tests: u64,
+}
+
+impl TraceCounters {
+    pub fn rays(&self) -> u64 {
+        self.path_rays + self.shadow_rays
+    }
+}
+
+impl AddAssign for TraceCounters {
+    fn add_assign(&mut self, other: TraceCounters) {
+        self.camera_rays += other.camera_rays;
+        self.path_rays += other.path_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.bvh_node_visits += other.bvh_node_visits;
+        self.primitive_tests += other.primitive_tests;
+    }
+}
+
+thread_local! {
+    static COUNTERS: Cell<TraceCounters> = Cell::new(TraceCounters::default());
+}
+
+/// Updates the counters of the calling thread.
+pub fn record(update: impl FnOnce(&mut TraceCounters)) {
+    COUNTERS.with(|counters| {
+        let mut c = counters.get();
+        update(&mut c);
+        counters.set(c);
+    });
+}
+
+/// Returns the counters of the calling thread and resets them.
+pub fn take() -> TraceCounters {
+    COUNTERS.with(|counters| counters.replace(TraceCounters::default()))
+}
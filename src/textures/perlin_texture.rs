@@ -4,19 +4,21 @@ use crate::random::perlin::Perlin;
 
 pub struct PerlinTexture {
     noise: Perlin,
+    scale: f32,
 }
 
 impl PerlinTexture {
-    #[allow(dead_code)]
-    pub fn new() -> Self {
+    /// Higher `scale` gives finer noise.
+    pub fn new(scale: f32) -> Self {
         Self {
             noise: Perlin::new(),
+            scale: scale,
         }
     }
 }
 
 impl Texture for PerlinTexture {
     fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
-        Vec3(1.0, 1.0, 1.0) * self.noise.noise(p)
+        Vec3(1.0, 1.0, 1.0) * self.noise.noise(&(self.scale * *p))
     }
 }
@@ -1,5 +1,6 @@
 use crate::linalg::Vec3;
 
+/// A colour varying over surface coordinates `(u, v)` or the hit point `p`.
 pub trait Texture: Send + Sync {
     fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
 }
@@ -0,0 +1,125 @@
+//! Splitting an image into the tiles worker threads render one at a time.
+
+/// The order tiles are handed out in, and so the order a preview fills in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    /// Row after row from the top, left to right.
+    Scanline,
+    /// Outwards from the centre of the image, where the subject usually is.
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other.
+    Hilbert,
+}
+
+impl TileOrder {
+    /// Looks an order up by the name used in scene files and on the command line.
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+/// The pixels `x..x + width` by `y..y + height`, `y` going up from the bottom row.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Covers `region` with tiles of `size` pixels, cut short along its right and bottom
+/// edges, in `order`.
+pub fn tiles(region: &Tile, size: usize, order: TileOrder) -> Vec<Tile> {
+    let columns = region.width.div_ceil(size);
+    let rows = region.height.div_ceil(size);
+    if columns == 0 || rows == 0 {
+        return Vec::new();
+    }
+    let cells = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => hilbert(columns, rows),
+    };
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let tile_height = size.min(region.height - row * size);
+            Tile {
+                x: region.x + column * size,
+                y: region.y + region.height - row * size - tile_height,
+                width: size.min(region.width - column * size),
+                height: tile_height,
+            }
+        })
+        .collect()
+}
+
+/// The cells of a `columns` by `rows` grid, rows counted from the top, walking a
+/// square spiral out of the middle one.
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let n = columns * rows;
+    let mut cells = Vec::with_capacity(n);
+    let (mut x, mut y) = (((columns - 1) / 2) as i64, ((rows - 1) / 2) as i64);
+    let visit = |x: i64, y: i64, cells: &mut Vec<(usize, usize)>| {
+        if x >= 0 && y >= 0 && (x as usize) < columns && (y as usize) < rows {
+            cells.push((x as usize, y as usize));
+        }
+    };
+    visit(x, y, &mut cells);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut direction = 0;
+    let mut run = 1;
+    while cells.len() < n {
+        // Runs grow by one every second turn.
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction];
+            for _ in 0..run {
+                x += dx;
+                y += dy;
+                visit(x, y, &mut cells);
+            }
+            direction = (direction + 1) % 4;
+        }
+        run += 1;
+    }
+    cells
+}
+
+/// The cells of a `columns` by `rows` grid in the order of the Hilbert curve over the
+/// smallest power-of-two square holding it.
+fn hilbert(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let side = columns.max(rows).next_power_of_two();
+    (0..side * side)
+        .map(|d| hilbert_point(side, d))
+        .filter(|&(x, y)| x < columns && y < rows)
+        .collect()
+}
+
+/// Point `d` of the Hilbert curve filling a `side` by `side` square.
+fn hilbert_point(side: usize, mut d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (d / 2);
+        let ry = 1 & (d ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        d /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
@@ -0,0 +1,121 @@
+//! Turning the linear radiance of a render into display values.
+
+use crate::linalg::Vec3;
+
+/// Compresses linear radiance into `[0, 1]`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapOperator {
+    /// Clips everything above 1.
+    Clamp,
+    /// `L / (1 + L)` on the luminance, so no radiance ever reaches white.
+    Reinhard,
+    /// Reinhard with a luminance that maps to white. Without one, the brightest
+    /// pixel of the image is used.
+    ExtendedReinhard { white: Option<f32> },
+    /// Narkowicz's fit of the ACES filmic curve, per channel.
+    ACES,
+}
+
+impl ToneMapOperator {
+    /// Looks an operator up by the name used in scene files and on the command line.
+    pub fn from_name(name: &str) -> Option<ToneMapOperator> {
+        match name {
+            "clamp" => Some(ToneMapOperator::Clamp),
+            "reinhard" => Some(ToneMapOperator::Reinhard),
+            "extended_reinhard" | "extended-reinhard" => {
+                Some(ToneMapOperator::ExtendedReinhard { white: None })
+            }
+            "aces" => Some(ToneMapOperator::ACES),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes values in `[0, 1]` for display.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransferFunction {
+    /// The piecewise sRGB curve.
+    SRGB,
+    /// A plain power of `1 / gamma`.
+    Gamma(f32),
+}
+
+/// The post-process stage applied to a render before it is stored in a display format.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMap {
+    /// Scale applied to the radiance first, in stops.
+    pub exposure: f32,
+    pub operator: ToneMapOperator,
+    pub transfer: TransferFunction,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            operator: ToneMapOperator::Clamp,
+            transfer: TransferFunction::SRGB,
+        }
+    }
+}
+
+fn luminance(r: f32, g: f32, b: f32) -> f32 {
+    Vec3::new(r, g, b).luminance()
+}
+
+fn srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+impl ToneMap {
+    /// Maps linear RGB triples to display values in `[0, 1]`.
+    pub fn apply(&self, data: &[f32]) -> Vec<f32> {
+        let scale = self.exposure.exp2();
+        // Negative and NaN samples would otherwise spread through the luminance.
+        let exposed = |x: f32| if x > 0.0 { x * scale } else { 0.0 };
+
+        let white = match self.operator {
+            ToneMapOperator::ExtendedReinhard { white: Some(w) } => w,
+            ToneMapOperator::ExtendedReinhard { white: None } => data
+                .chunks(3)
+                .map(|px| luminance(exposed(px[0]), exposed(px[1]), exposed(px[2])))
+                .fold(0.0, f32::max),
+            _ => 1.0,
+        }
+        .max(1e-6);
+
+        let mut out = Vec::with_capacity(data.len());
+        for px in data.chunks(3) {
+            let (r, g, b) = (exposed(px[0]), exposed(px[1]), exposed(px[2]));
+            let mapped = match self.operator {
+                ToneMapOperator::Clamp => [r, g, b],
+                ToneMapOperator::ACES => [aces(r), aces(g), aces(b)],
+                ToneMapOperator::Reinhard | ToneMapOperator::ExtendedReinhard { .. } => {
+                    let l = luminance(r, g, b);
+                    let mapped_l = match self.operator {
+                        ToneMapOperator::Reinhard => l / (1.0 + l),
+                        _ => l * (1.0 + l / (white * white)) / (1.0 + l),
+                    };
+                    let k = if l > 0.0 { mapped_l / l } else { 0.0 };
+                    [r * k, g * k, b * k]
+                }
+            };
+            for x in mapped.iter() {
+                let x = x.clamp(0.0, 1.0);
+                out.push(match self.transfer {
+                    TransferFunction::SRGB => srgb(x),
+                    TransferFunction::Gamma(gamma) => x.powf(1.0 / gamma),
+                });
+            }
+        }
+        out
+    }
+}